@@ -1,42 +1,198 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
 use thiserror::Error;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TaskQuery {
     pub done: Option<bool>,
     pub priority: Option<Priority>,
+    /// Keyset cursor: only return tasks with an id greater than this
+    pub after_id: Option<i64>,
+    /// Page size; the response becomes a `TaskPage` carrying the next page's
+    /// cursor, which is also sent in the `X-Next-Cursor` header
+    pub limit: Option<i64>,
+    /// Case-insensitive match on the task text, how is set by `match_mode`
+    pub search: Option<String>,
+    /// How `search` is matched against the text, substring when unset
+    pub match_mode: Option<MatchMode>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+pub enum MatchMode {
+    /// `inv` matches "invoice" and "Kevin"
+    #[default]
+    Substring,
+    /// `inv` matches "invoice" but not "Kevin"
+    Prefix,
+    /// The whole text has to match, ignoring case
+    Exact,
+}
+
+impl MatchMode {
+    /// Cycles Substring -> Prefix -> Exact and back around
+    pub fn next(self) -> MatchMode {
+        match self {
+            MatchMode::Substring => MatchMode::Prefix,
+            MatchMode::Prefix => MatchMode::Exact,
+            MatchMode::Exact => MatchMode::Substring,
+        }
+    }
+}
+
+impl std::fmt::Display for MatchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchMode::Substring => write!(f, "substring"),
+            MatchMode::Prefix => write!(f, "prefix"),
+            MatchMode::Exact => write!(f, "exact"),
+        }
+    }
 }
 
 impl From<Filter> for TaskQuery {
     fn from(filter: Filter) -> Self {
         match filter {
-            Filter::All => TaskQuery {
-                done: None,
-                priority: None,
-            },
+            Filter::All => TaskQuery::default(),
             Filter::Todo => TaskQuery {
                 done: Some(false),
-                priority: None,
+                ..Default::default()
             },
             Filter::Done => TaskQuery {
                 done: Some(true),
-                priority: None,
+                ..Default::default()
             },
             Filter::Priority(priority) => TaskQuery {
-                done: None,
                 priority: Some(priority),
+                ..Default::default()
             },
         }
     }
 }
 
+/// One page of `GET /todos` when a `limit` is set
+#[derive(Default, Deserialize, Serialize, Debug)]
+pub struct TaskPage {
+    pub tasks: Vec<Task>,
+    /// Pass as `after_id` for the next page, `None` on the last one
+    pub next_cursor: Option<i64>,
+}
+
 #[derive(Default, Clone, Deserialize, Serialize, Debug)]
 #[cfg_attr(feature = "backend", derive(sqlx::FromRow))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
 pub struct Task {
     pub id: i64,
     pub text: String,
     pub done: bool,
     pub priority: Priority,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub due: Option<DateTime<Utc>>,
+    /// Task that has to be finished before this one can be
+    #[serde(default)]
+    pub depends_on: Option<i64>,
+    /// Whether `depends_on` points at a task that isn't done yet
+    #[serde(default)]
+    pub blocked: bool,
+    #[serde(default)]
+    pub estimate_minutes: Option<i32>,
+    #[serde(default)]
+    pub spent_minutes: Option<i32>,
+    /// Free-form colour tag, e.g. "red", for grouping tasks at a glance
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Bumped on every change, sent back on updates to detect lost writes
+    #[serde(default)]
+    pub version: i64,
+    /// Client that created the task, e.g. "tui", if it said
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Longer notes, e.g. the body of the email a task was created from
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Free-form tags like "shopping", in the order they were added
+    #[serde(default)]
+    #[cfg_attr(feature = "backend", sqlx(json))]
+    pub tags: Vec<String>,
+    /// Not stored, only filled in when tasks are fetched with `with_urgency=true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "backend", sqlx(skip))]
+    pub urgency: Option<Urgency>,
+}
+
+/// How close an unfinished task is to its due date
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+pub enum Urgency {
+    Overdue,
+    /// Due within the next day
+    DueSoon,
+    Normal,
+}
+
+impl Urgency {
+    /// Done tasks and ones without a due date are always `Normal`
+    pub fn of(task: &Task, now: DateTime<Utc>) -> Urgency {
+        match task.due {
+            Some(due) if !task.done && due < now => Urgency::Overdue,
+            Some(due) if !task.done && due - now <= TimeDelta::days(1) => Urgency::DueSoon,
+            _ => Urgency::Normal,
+        }
+    }
+}
+
+/// How timestamps are shown to people
+#[derive(Clone, Copy, Default, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DateFormat {
+    /// `just now`, `5m ago`, `in 3h`, `2d ago`
+    #[default]
+    Relative,
+    /// `2024-06-01 14:00` in the timezone of the timestamp given
+    Absolute,
+}
+
+impl FromStr for DateFormat {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "relative" => Ok(DateFormat::Relative),
+            "absolute" => Ok(DateFormat::Absolute),
+            _ => Err(TodoError::DateFormatError),
+        }
+    }
+}
+
+/// Formats `when` for display. Relative times are rounded down to the
+/// largest whole unit, minutes, hours or days, and anything under a minute
+/// either way is `just now`.
+pub fn format_when<Tz: TimeZone>(when: DateTime<Tz>, now: DateTime<Utc>, mode: DateFormat) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    if mode == DateFormat::Absolute {
+        return when.format("%Y-%m-%d %H:%M").to_string();
+    }
+    let delta = when.with_timezone(&Utc) - now;
+    let abs = delta.abs();
+    let amount = if abs < TimeDelta::minutes(1) {
+        return "just now".to_string();
+    } else if abs < TimeDelta::hours(1) {
+        format!("{}m", abs.num_minutes())
+    } else if abs < TimeDelta::days(1) {
+        format!("{}h", abs.num_hours())
+    } else {
+        format!("{}d", abs.num_days())
+    };
+    if delta < TimeDelta::zero() {
+        format!("{amount} ago")
+    } else {
+        format!("in {amount}")
+    }
 }
 
 impl std::fmt::Display for Task {
@@ -46,9 +202,23 @@ impl std::fmt::Display for Task {
     }
 }
 
-#[derive(Clone, Copy, Default, Deserialize, Serialize, Debug, PartialEq)]
-#[cfg_attr(feature = "backend", derive(sqlx::Type))]
-#[cfg_attr(feature = "backend", sqlx(type_name = "TEXT"))]
+#[cfg(feature = "cli")]
+impl Task {
+    /// Same layout as `Display`, with the priority coloured using ANSI escapes
+    /// (High red, Medium yellow, Low unstyled) for terminal output
+    pub fn to_colored_string(&self) -> String {
+        let status = if self.done { "[x]" } else { "[ ]" };
+        let priority = match self.priority {
+            Priority::Low => self.priority.to_string(),
+            Priority::Medium => format!("\x1b[33m{}\x1b[0m", self.priority),
+            Priority::High => format!("\x1b[31m{}\x1b[0m", self.priority),
+        };
+        format!("{status} {priority} {}: {}", self.id, self.text)
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
 pub enum Priority {
     #[default]
     Low,
@@ -66,6 +236,104 @@ impl std::fmt::Display for Priority {
     }
 }
 
+impl Priority {
+    /// Stable numeric form for sorting or storing outside this app, Low = 0
+    /// through High = 2
+    pub fn weight(&self) -> u8 {
+        match self {
+            Priority::Low => 0,
+            Priority::Medium => 1,
+            Priority::High => 2,
+        }
+    }
+
+    /// One step higher, staying at High rather than wrapping around
+    pub fn raised(self) -> Priority {
+        Priority::from_weight(self.weight() + 1).unwrap_or(self)
+    }
+
+    /// One step lower, staying at Low rather than wrapping around
+    pub fn lowered(self) -> Priority {
+        self.weight()
+            .checked_sub(1)
+            .and_then(Priority::from_weight)
+            .unwrap_or(self)
+    }
+
+    pub fn from_weight(weight: u8) -> Option<Priority> {
+        match weight {
+            0 => Some(Priority::Low),
+            1 => Some(Priority::Medium),
+            2 => Some(Priority::High),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "backend")]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "Low",
+            Priority::Medium => "Medium",
+            Priority::High => "High",
+        }
+    }
+}
+
+#[cfg(feature = "backend")]
+impl sqlx::Type<sqlx::Sqlite> for Priority {
+    fn type_info() -> sqlx::sqlite::SqliteTypeInfo {
+        <str as sqlx::Type<sqlx::Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &sqlx::sqlite::SqliteTypeInfo) -> bool {
+        <str as sqlx::Type<sqlx::Sqlite>>::compatible(ty)
+    }
+}
+
+#[cfg(feature = "backend")]
+impl<'q> sqlx::Encode<'q, sqlx::Sqlite> for Priority {
+    fn encode_by_ref(
+        &self,
+        args: &mut Vec<sqlx::sqlite::SqliteArgumentValue<'q>>,
+    ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+        <&str as sqlx::Encode<sqlx::Sqlite>>::encode(self.as_str(), args)
+    }
+}
+
+// a bad value in a single row shouldn't fail the whole query, so anything
+// unrecognised decodes as Low instead of erroring
+#[cfg(feature = "backend")]
+impl<'r> sqlx::Decode<'r, sqlx::Sqlite> for Priority {
+    fn decode(value: sqlx::sqlite::SqliteValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        let value = <&str as sqlx::Decode<sqlx::Sqlite>>::decode(value)?;
+        let priority = match value.to_ascii_lowercase().as_str() {
+            "low" => Priority::Low,
+            "medium" => Priority::Medium,
+            "high" => Priority::High,
+            _ => {
+                tracing::warn!("unknown priority {value:?} in database, treating as Low");
+                Priority::Low
+            }
+        };
+        Ok(priority)
+    }
+}
+
+/// Parses either the variant name, case-insensitively, or the `(L)`/`(M)`/`(H)`
+/// form produced by `Display`
+impl FromStr for Priority {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "low" | "(l)" => Ok(Priority::Low),
+            "medium" | "(m)" => Ok(Priority::Medium),
+            "high" | "(h)" => Ok(Priority::High),
+            _ => Err(TodoError::PriorityError),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum TodoError {
     #[error("invalid command")]
@@ -80,9 +348,15 @@ pub enum TodoError {
     SaveError(#[source] std::io::Error),
     #[error("unknown priority")]
     PriorityError,
+    #[error("unknown filter")]
+    FilterError,
+    #[error("unknown date format")]
+    DateFormatError,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize, Default, Serialize, PartialEq)]
+/// Serialized as its `Display` string (e.g. `"Todo"` or `"Priority (H)"`) so it
+/// reads naturally in config and state files and round-trips through `FromStr`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum Filter {
     #[default]
     All,
@@ -101,3 +375,375 @@ impl std::fmt::Display for Filter {
         }
     }
 }
+
+impl FromStr for Filter {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(Filter::All),
+            "todo" => Ok(Filter::Todo),
+            "done" => Ok(Filter::Done),
+            _ => {
+                let (prefix, priority) = s
+                    .split_at_checked("priority".len())
+                    .ok_or(TodoError::FilterError)?;
+                if !prefix.eq_ignore_ascii_case("priority") {
+                    return Err(TodoError::FilterError);
+                }
+                priority
+                    .parse()
+                    .map(Filter::Priority)
+                    .map_err(|_| TodoError::FilterError)
+            }
+        }
+    }
+}
+
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Pulls Todoist-style tokens out of new task text: `!high` (anything
+/// `Priority` parses) sets the priority and `#shopping` adds a tag. Other
+/// words, including ones like `!bogus`, are kept as text. If that would leave
+/// no text at all, the input is kept as it is instead.
+pub fn parse_quick_add(input: &str) -> (String, Option<Priority>, Vec<String>) {
+    let mut words = Vec::new();
+    let mut priority = None;
+    let mut tags: Vec<String> = Vec::new();
+    for word in input.split_whitespace() {
+        if let Some(Ok(parsed)) = word.strip_prefix('!').map(str::parse) {
+            priority = Some(parsed);
+        } else if let Some(tag) = word.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        } else {
+            words.push(word);
+        }
+    }
+    if words.is_empty() {
+        return (input.trim().to_string(), None, Vec::new());
+    }
+    (words.join(" "), priority, tags)
+}
+
+/// A one-line command, as typed into a CLI or a chat slash command
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Add(String),
+    Done(i64),
+    Remove(i64),
+    SetPriority(i64, Priority),
+    List(Filter),
+}
+
+/// Parses `add <text>`, `done <id>`, `remove <id>`, `priority <id> <priority>`
+/// or `list [filter]`. Verbs ignore case, ids may be written `#12`, and `list`
+/// alone lists everything.
+pub fn parse_command(input: &str) -> Result<Command, TodoError> {
+    let input = input.trim();
+    let (verb, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let rest = rest.trim();
+    let id = |arg: Option<&str>| -> Result<i64, TodoError> {
+        let arg = arg.ok_or(TodoError::MissingArgument)?;
+        let arg = arg.strip_prefix('#').unwrap_or(arg);
+        // NonZeroU64 refuses 0 and negatives, then i64 anything too large
+        arg.parse::<std::num::NonZeroU64>()?;
+        Ok(arg.parse()?)
+    };
+    match verb.to_ascii_lowercase().as_str() {
+        "add" if rest.is_empty() => Err(TodoError::MissingArgument),
+        "add" => Ok(Command::Add(rest.to_string())),
+        "done" => Ok(Command::Done(id(rest.split_whitespace().next())?)),
+        "remove" => Ok(Command::Remove(id(rest.split_whitespace().next())?)),
+        "priority" => {
+            let mut args = rest.split_whitespace();
+            let id = id(args.next())?;
+            let priority = args.next().ok_or(TodoError::MissingArgument)?.parse()?;
+            Ok(Command::SetPriority(id, priority))
+        }
+        "list" if rest.is_empty() => Ok(Command::List(Filter::All)),
+        "list" => Ok(Command::List(rest.parse()?)),
+        _ => Err(TodoError::UnknownCommand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quick_add_tokens() {
+        assert_eq!(
+            parse_quick_add("buy milk !high #shopping"),
+            (
+                "buy milk".to_string(),
+                Some(Priority::High),
+                vec!["shopping".to_string()]
+            )
+        );
+        assert_eq!(
+            parse_quick_add("#home fix  sink #home #urgent !low"),
+            (
+                "fix sink".to_string(),
+                Some(Priority::Low),
+                vec!["home".to_string(), "urgent".to_string()]
+            )
+        );
+        assert_eq!(
+            parse_quick_add("learn !bogus and C# or #"),
+            ("learn !bogus and C# or #".to_string(), None, vec![])
+        );
+        assert_eq!(
+            parse_quick_add(" #only !high "),
+            ("#only !high".to_string(), None, vec![])
+        );
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            parse_command(" add  buy milk ").unwrap(),
+            Command::Add("buy milk".to_string())
+        );
+        assert_eq!(parse_command("Done 3").unwrap(), Command::Done(3));
+        assert_eq!(parse_command("done #12").unwrap(), Command::Done(12));
+        assert_eq!(parse_command("remove 4").unwrap(), Command::Remove(4));
+        assert_eq!(
+            parse_command("priority 5 high").unwrap(),
+            Command::SetPriority(5, Priority::High)
+        );
+        assert_eq!(parse_command("list").unwrap(), Command::List(Filter::All));
+        assert_eq!(
+            parse_command("list priority (h)").unwrap(),
+            Command::List(Filter::Priority(Priority::High))
+        );
+    }
+
+    #[test]
+    fn todo_error_messages() {
+        let message = |input: &str| parse_command(input).unwrap_err().to_string();
+        assert_eq!(message("fly"), "invalid command");
+        assert_eq!(message("done"), "invalid arguments");
+        assert_eq!(message("done x"), "task id must be a positive integer");
+        assert_eq!(message("priority 1 urgent"), "unknown priority");
+        assert_eq!(message("list someday"), "unknown filter");
+        assert_eq!(
+            TodoError::TaskNotFound.to_string(),
+            "task with that id was not found"
+        );
+        let io = std::io::Error::other("disk full");
+        assert_eq!(
+            TodoError::SaveError(io).to_string(),
+            "failed to save todo list"
+        );
+        assert_eq!(
+            "someday".parse::<DateFormat>().unwrap_err().to_string(),
+            "unknown date format"
+        );
+    }
+
+    #[test]
+    fn parse_command_errors() {
+        assert!(matches!(
+            parse_command("fly away"),
+            Err(TodoError::UnknownCommand)
+        ));
+        assert!(matches!(parse_command(""), Err(TodoError::UnknownCommand)));
+        assert!(matches!(
+            parse_command("add"),
+            Err(TodoError::MissingArgument)
+        ));
+        assert!(matches!(
+            parse_command("priority 5"),
+            Err(TodoError::MissingArgument)
+        ));
+        for bad in [
+            "done soon",
+            "done -5",
+            "done 0",
+            "remove #",
+            "done 9223372036854775808",
+        ] {
+            assert!(
+                matches!(parse_command(bad), Err(TodoError::InvalidId(_))),
+                "{bad} should be refused"
+            );
+        }
+        assert!(matches!(
+            parse_command("priority 5 urgent"),
+            Err(TodoError::PriorityError)
+        ));
+        assert!(matches!(
+            parse_command("list later"),
+            Err(TodoError::FilterError)
+        ));
+    }
+
+    #[test]
+    fn priority_display() {
+        assert_eq!(Priority::Low.to_string(), "(L)");
+        assert_eq!(Priority::Medium.to_string(), "(M)");
+        assert_eq!(Priority::High.to_string(), "(H)");
+    }
+
+    #[test]
+    fn priority_weight_round_trip() {
+        for priority in [Priority::Low, Priority::Medium, Priority::High] {
+            assert_eq!(Priority::from_weight(priority.weight()), Some(priority));
+        }
+        assert!(Priority::Low.weight() < Priority::Medium.weight());
+        assert!(Priority::Medium.weight() < Priority::High.weight());
+        assert_eq!(Priority::from_weight(3), None);
+    }
+
+    #[test]
+    fn priority_steps_saturate() {
+        assert_eq!(Priority::Low.raised(), Priority::Medium);
+        assert_eq!(Priority::Medium.raised(), Priority::High);
+        assert_eq!(Priority::High.raised(), Priority::High);
+        assert_eq!(Priority::High.lowered(), Priority::Medium);
+        assert_eq!(Priority::Medium.lowered(), Priority::Low);
+        assert_eq!(Priority::Low.lowered(), Priority::Low);
+    }
+
+    #[test]
+    fn task_display() {
+        let mut task = Task {
+            id: 3,
+            text: "buy milk".to_string(),
+            done: false,
+            priority: Priority::High,
+            ..Default::default()
+        };
+        assert_eq!(task.to_string(), "[ ] (H) 3: buy milk");
+
+        task.done = true;
+        assert_eq!(task.to_string(), "[x] (H) 3: buy milk");
+    }
+
+    #[test]
+    fn urgency_from_due() {
+        let now = "2026-10-17T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut task = Task::default();
+        assert_eq!(Urgency::of(&task, now), Urgency::Normal);
+
+        task.due = Some(now - TimeDelta::hours(1));
+        assert_eq!(Urgency::of(&task, now), Urgency::Overdue);
+        task.due = Some(now + TimeDelta::hours(3));
+        assert_eq!(Urgency::of(&task, now), Urgency::DueSoon);
+        task.due = Some(now + TimeDelta::days(3));
+        assert_eq!(Urgency::of(&task, now), Urgency::Normal);
+
+        task.due = Some(now - TimeDelta::hours(1));
+        task.done = true;
+        assert_eq!(Urgency::of(&task, now), Urgency::Normal);
+    }
+
+    #[test]
+    fn relative_times_use_the_largest_whole_unit() {
+        let now = "2026-10-17T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let relative = |delta| format_when(now + delta, now, DateFormat::Relative);
+        assert_eq!(relative(TimeDelta::seconds(-59)), "just now");
+        assert_eq!(relative(TimeDelta::seconds(30)), "just now");
+        assert_eq!(relative(TimeDelta::seconds(-90)), "1m ago");
+        assert_eq!(relative(TimeDelta::minutes(59)), "in 59m");
+        assert_eq!(relative(TimeDelta::minutes(-150)), "2h ago");
+        assert_eq!(relative(TimeDelta::hours(23)), "in 23h");
+        assert_eq!(relative(TimeDelta::hours(-47)), "1d ago");
+        assert_eq!(relative(TimeDelta::days(12)), "in 12d");
+    }
+
+    #[test]
+    fn absolute_times_keep_their_timezone() {
+        let now = "2026-10-17T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let offset = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+        assert_eq!(
+            format_when(now, now, DateFormat::Absolute),
+            "2026-10-17 12:00"
+        );
+        assert_eq!(
+            format_when(now.with_timezone(&offset), now, DateFormat::Absolute),
+            "2026-10-17 14:00"
+        );
+        assert_eq!(
+            " Absolute ".parse::<DateFormat>().unwrap(),
+            DateFormat::Absolute
+        );
+    }
+
+    #[test]
+    fn priority_from_str() {
+        assert_eq!("high".parse::<Priority>().unwrap(), Priority::High);
+        assert_eq!("Medium".parse::<Priority>().unwrap(), Priority::Medium);
+        for priority in [Priority::Low, Priority::Medium, Priority::High] {
+            assert_eq!(priority.to_string().parse::<Priority>().unwrap(), priority);
+        }
+        assert!(matches!(
+            "urgent".parse::<Priority>(),
+            Err(TodoError::PriorityError)
+        ));
+    }
+
+    #[test]
+    fn match_mode_cycles_back_to_default() {
+        let mode = MatchMode::default();
+        assert_eq!(mode.next(), MatchMode::Prefix);
+        assert_eq!(mode.next().next(), MatchMode::Exact);
+        assert_eq!(mode.next().next().next(), mode);
+    }
+
+    #[test]
+    fn filter_string_round_trip() {
+        let filters = [
+            Filter::All,
+            Filter::Todo,
+            Filter::Done,
+            Filter::Priority(Priority::Low),
+            Filter::Priority(Priority::Medium),
+            Filter::Priority(Priority::High),
+        ];
+        for filter in filters {
+            assert_eq!(filter.to_string().parse::<Filter>().unwrap(), filter);
+        }
+        assert_eq!(
+            "priority high".parse::<Filter>().unwrap(),
+            Filter::Priority(Priority::High)
+        );
+        assert!(matches!(
+            "someday".parse::<Filter>(),
+            Err(TodoError::FilterError)
+        ));
+        assert!(matches!(
+            "priority".parse::<Filter>(),
+            Err(TodoError::FilterError)
+        ));
+    }
+
+    #[test]
+    fn filter_toml_round_trip() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct State {
+            filter: Filter,
+        }
+
+        let state = State {
+            filter: Filter::Priority(Priority::Medium),
+        };
+        let toml = toml::to_string(&state).unwrap();
+        assert_eq!(toml.trim(), r#"filter = "Priority (M)""#);
+        assert_eq!(toml::from_str::<State>(&toml).unwrap(), state);
+    }
+}
@@ -0,0 +1,2 @@
+ALTER TABLE tasks ADD COLUMN estimate_minutes INTEGER;
+ALTER TABLE tasks ADD COLUMN spent_minutes INTEGER;
@@ -0,0 +1,15 @@
+-- Kept up to date by triggers so every write path is covered, and stored in
+-- a fixed format so timestamps compare correctly as text
+ALTER TABLE tasks ADD COLUMN updated_at TEXT;
+UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+CREATE INDEX tasks_updated_at ON tasks (updated_at);
+
+CREATE TRIGGER tasks_touch_insert AFTER INSERT ON tasks BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+-- an update that sets updated_at itself keeps it
+CREATE TRIGGER tasks_touch_update AFTER UPDATE ON tasks
+WHEN NEW.updated_at IS OLD.updated_at BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
@@ -0,0 +1,13 @@
+CREATE TABLE templates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+-- a NULL priority means the server's default when the template is applied
+CREATE TABLE template_tasks (
+    template_id INTEGER NOT NULL REFERENCES templates(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    priority TEXT,
+    PRIMARY KEY (template_id, position)
+);
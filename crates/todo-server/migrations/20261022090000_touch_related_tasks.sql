@@ -0,0 +1,29 @@
+-- a task's dependency, tags and blocked state live outside its row, so
+-- changing any of them bumps updated_at on the task it shows up on
+CREATE TRIGGER task_deps_touch_insert AFTER INSERT ON task_deps BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.task_id;
+END;
+
+CREATE TRIGGER task_deps_touch_update AFTER UPDATE ON task_deps BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.task_id;
+END;
+
+CREATE TRIGGER task_deps_touch_delete AFTER DELETE ON task_deps BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = OLD.task_id;
+END;
+
+CREATE TRIGGER task_tags_touch_insert AFTER INSERT ON task_tags BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.task_id;
+END;
+
+CREATE TRIGGER task_tags_touch_delete AFTER DELETE ON task_tags BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = OLD.task_id;
+END;
+
+-- finishing, reopening, trashing or restoring a task can block or unblock
+-- the tasks that depend on it
+CREATE TRIGGER tasks_touch_dependents AFTER UPDATE OF done, deleted_at ON tasks
+WHEN NEW.done IS NOT OLD.done OR NEW.deleted_at IS NOT OLD.deleted_at BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+    WHERE id IN (SELECT task_id FROM task_deps WHERE depends_on = NEW.id);
+END;
@@ -0,0 +1,153 @@
+use async_graphql::{Context, EmptySubscription, MaybeUndefined, Object, Result, Schema};
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use todo_common::{MatchMode, Priority, Task, TaskQuery};
+
+use crate::{
+    CreateTodo, READ_ONLY_MESSAGE, SearchIndex, UpdateTodo, apply_update, insert_task, remove_task,
+    select_tasks,
+};
+
+pub type TodoSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Whether the server was started with `READ_ONLY=1`
+struct ReadOnly(bool);
+
+/// The `MAX_TASKS` cap, if any
+struct MaxTasks(Option<u64>);
+
+/// GraphQL view over the same tasks table as the REST routes, sharing their
+/// query and mutation helpers
+pub fn schema(
+    pool: SqlitePool,
+    default_priority: Priority,
+    search_index: SearchIndex,
+    read_only: bool,
+    max_tasks: Option<u64>,
+) -> TodoSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(pool)
+        .data(default_priority)
+        .data(search_index)
+        .data(ReadOnly(read_only))
+        .data(MaxTasks(max_tasks))
+        .finish()
+}
+
+/// Mutations start with this so a read-only server refuses them the way the
+/// REST routes do
+fn check_writable(ctx: &Context<'_>) -> Result<()> {
+    if ctx.data::<ReadOnly>()?.0 {
+        return Err(READ_ONLY_MESSAGE.into());
+    }
+    Ok(())
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Tasks matching the given filters, the same ones `GET /todos` accepts
+    async fn tasks(
+        &self,
+        ctx: &Context<'_>,
+        done: Option<bool>,
+        priority: Option<Priority>,
+        search: Option<String>,
+        match_mode: Option<MatchMode>,
+    ) -> Result<Vec<Task>> {
+        let pool = ctx.data::<SqlitePool>()?;
+        let search_index = *ctx.data::<SearchIndex>()?;
+        let params = TaskQuery {
+            done,
+            priority,
+            search,
+            match_mode,
+            ..Default::default()
+        };
+        Ok(select_tasks(pool, &params, search_index, false).await?)
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// `source` names the creating client and defaults to "graphql". Like
+    /// `POST /todos`, `!high` and `#tag` in the text set priority and tags.
+    #[allow(clippy::too_many_arguments)]
+    async fn create_task(
+        &self,
+        ctx: &Context<'_>,
+        text: String,
+        priority: Option<Priority>,
+        due: Option<DateTime<Utc>>,
+        depends_on: Option<i64>,
+        source: Option<String>,
+        description: Option<String>,
+    ) -> Result<Task> {
+        check_writable(ctx)?;
+        let pool = ctx.data::<SqlitePool>()?;
+        let default_priority = *ctx.data::<Priority>()?;
+        let mut payload = CreateTodo {
+            text,
+            priority,
+            due,
+            depends_on,
+            source: source.or_else(|| Some("graphql".to_string())),
+            description,
+            tags: Vec::new(),
+        };
+        payload.parse_quick_add();
+        let max_tasks = ctx.data::<MaxTasks>()?.0;
+        Ok(insert_task(pool, payload, default_priority, max_tasks).await?)
+    }
+
+    /// Returns false if no task has that id, and errors when marking a task
+    /// done while its dependency is unfinished. An empty label clears it.
+    /// Passing the `version` last seen makes it fail if the task changed since.
+    /// `clear_due` removes the due date, and a null `depends_on` the dependency.
+    #[allow(clippy::too_many_arguments)]
+    async fn update_task(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        text: Option<String>,
+        done: Option<bool>,
+        priority: Option<Priority>,
+        due: Option<DateTime<Utc>>,
+        clear_due: Option<bool>,
+        depends_on: MaybeUndefined<i64>,
+        estimate_minutes: Option<i32>,
+        spent_minutes: Option<i32>,
+        label: Option<String>,
+        version: Option<i64>,
+    ) -> Result<bool> {
+        check_writable(ctx)?;
+        let pool = ctx.data::<SqlitePool>()?;
+        let payload = UpdateTodo {
+            text,
+            done,
+            priority,
+            due,
+            clear_due: clear_due.unwrap_or_default(),
+            depends_on: match depends_on {
+                MaybeUndefined::Undefined => None,
+                MaybeUndefined::Null => Some(None),
+                MaybeUndefined::Value(id) => Some(Some(id)),
+            },
+            estimate_minutes,
+            spent_minutes,
+            label,
+            version,
+        };
+        Ok(apply_update(pool, id, payload).await?)
+    }
+
+    /// Moves the task to the trash, returning false if no task has that id
+    async fn delete_task(&self, ctx: &Context<'_>, id: i64) -> Result<bool> {
+        check_writable(ctx)?;
+        let pool = ctx.data::<SqlitePool>()?;
+        Ok(remove_task(pool, id).await?)
+    }
+}
@@ -1,135 +1,3480 @@
+use async_graphql_axum::GraphQL;
 use axum::{
-    Json, Router,
-    extract::{Path, Query, State},
+    BoxError, Json, Router,
+    body::Body,
+    error_handling::HandleErrorLayer,
+    extract::{Form, FromRequest, Path, Query, Request, State, rejection::JsonRejection},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::delete,
     routing::get,
     routing::patch,
+    routing::post,
+    routing::post_service,
 };
-use serde::Deserialize;
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use futures_util::StreamExt;
+use metrics::{counter, gauge};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use serde::{Deserialize, Serialize};
 use sqlx::query_builder::QueryBuilder;
-use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
-use todo_common::{Priority, Task, TaskQuery};
+use std::time::Duration;
+use todo_common::{
+    Command, MatchMode, Priority, Task, TaskPage, TaskQuery, Urgency, parse_command,
+    parse_quick_add,
+};
+use tower::ServiceBuilder;
+use tower::timeout::TimeoutLayer;
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::trace::TraceLayer;
-use tracing::{info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod graphql;
+
+// depends_on and blocked come from task_deps, so every query returning tasks
+// picks them up without needing a join. A trashed dependency no longer
+// blocks, since soft delete leaves the task_deps row in place.
+const TASK_COLUMNS: &str =
+    "id, text, done, priority, completed_at, due, estimate_minutes, spent_minutes, label, version, source, description,
+    (SELECT depends_on FROM task_deps WHERE task_id = tasks.id) AS depends_on,
+    (SELECT json_group_array(tag) FROM (SELECT tag FROM task_tags
+        WHERE task_id = tasks.id ORDER BY rowid)) AS tags,
+    EXISTS (SELECT 1 FROM task_deps JOIN tasks AS dep ON dep.id = task_deps.depends_on
+        WHERE task_deps.task_id = tasks.id AND NOT dep.done AND dep.deleted_at IS NULL) AS blocked";
+
+const JSON_API_MEDIA_TYPE: &str = "application/vnd.api+json";
+/// Lets a client name itself as a new task's source without touching the body
+const SOURCE_HEADER: &str = "x-todo-source";
+
+const READ_ONLY_MESSAGE: &str = "the server is read-only, tasks can't be changed";
+
+// a task is a line of text, so anything near this size is a mistake or abuse
+const MAX_BODY_BYTES: usize = 64 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 5;
+const CONNECT_BACKOFF_START: Duration = Duration::from_millis(250);
+const CONNECT_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
 struct AppState {
     pool: sqlx::SqlitePool,
+    metrics: PrometheusHandle,
+    dev_mode: bool,
+    default_priority: Priority,
+    backup_dir: PathBuf,
+    search_index: SearchIndex,
+    /// Cap on tasks outside the trash, `None` for no limit
+    max_tasks: Option<u64>,
+    /// Set by `READ_ONLY=1`, refuses every request that would change tasks
+    read_only: bool,
+}
+
+/// How `search` is run. FTS5 is used when SQLite was built with it, and LIKE
+/// is the fallback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SearchIndex {
+    Like,
+    Fts,
+}
+
+#[derive(Deserialize, Debug)]
+struct DebugParams {
+    #[serde(default)]
+    debug: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChangesParams {
+    since: DateTime<Utc>,
+}
+
+/// What changed since a sync client last asked. Tasks still in the list
+/// are upserts and trashed ones tombstones, purged tasks aren't reported.
+#[derive(Serialize, Debug)]
+struct Changes {
+    upserts: Vec<Task>,
+    tombstones: Vec<i64>,
+    /// Pass as `since` next time to pick up where this left off
+    as_of: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PrettyParams {
+    #[serde(default)]
+    pretty: bool,
+}
+
+/// Responds like `Json`, but indented when the client asked for
+/// `?pretty=true`, which is easier to read when debugging with curl
+struct MaybePretty<T> {
+    value: T,
+    pretty: bool,
+}
+
+impl<T: Serialize> IntoResponse for MaybePretty<T> {
+    fn into_response(self) -> Response {
+        if !self.pretty {
+            return Json(self.value).into_response();
+        }
+        match json_bytes(&self.value, true) {
+            Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+fn json_bytes<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<Vec<u8>> {
+    if pretty {
+        serde_json::to_vec_pretty(value)
+    } else {
+        serde_json::to_vec(value)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ConfirmParams {
+    #[serde(default)]
+    confirm: bool,
 }
 
 #[derive(Deserialize, Debug)]
+struct UrgencyParams {
+    #[serde(default)]
+    with_urgency: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
 struct CreateTodo {
     text: String,
+    #[serde(default, deserialize_with = "priority_by_name")]
+    priority: Option<Priority>,
+    due: Option<DateTime<Utc>>,
+    depends_on: Option<i64>,
+    /// Which client created the task, e.g. "tui"
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl CreateTodo {
+    /// Moves `!priority` and `#tag` tokens out of the text, as in
+    /// "buy milk !high #shopping". A priority given separately still wins.
+    fn parse_quick_add(&mut self) {
+        let (text, priority, tags) = parse_quick_add(&self.text);
+        self.text = text;
+        self.priority = self.priority.or(priority);
+        self.tags.extend(tags);
+    }
+}
+
+/// Reads a priority by name, case-insensitively, so a typo like `"urgent"`
+/// is refused with the valid values rather than serde's variant message
+fn priority_by_name<'de, D>(deserializer: D) -> Result<Option<Priority>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(name) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    name.parse().map(Some).map_err(|_| {
+        serde::de::Error::custom(format!(
+            "unknown priority {name:?}, expected one of Low, Medium, High"
+        ))
+    })
 }
 
+/// The parts of a parsed email needed to turn it into a task
 #[derive(Deserialize, Debug)]
+struct EmailTodo {
+    subject: String,
+    #[serde(default)]
+    body: String,
+}
+
+impl EmailTodo {
+    /// The subject becomes the text and the body the description. `[URGENT]`
+    /// anywhere in the subject makes the task high priority and is dropped
+    /// from the text. `None` if nothing is left of the subject.
+    fn into_task(self) -> Option<CreateTodo> {
+        const URGENT: &str = "[URGENT]";
+        let mut subject = self.subject;
+        // ASCII uppercasing keeps byte offsets valid for the original
+        let priority = match subject.to_ascii_uppercase().find(URGENT) {
+            Some(start) => {
+                subject.replace_range(start..start + URGENT.len(), "");
+                Some(Priority::High)
+            }
+            None => None,
+        };
+        let text = subject.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            return None;
+        }
+        let body = self.body.trim();
+        Some(CreateTodo {
+            text,
+            priority,
+            due: None,
+            depends_on: None,
+            source: Some("email".to_string()),
+            description: (!body.is_empty()).then(|| body.to_string()),
+            tags: Vec::new(),
+        })
+    }
+}
+
+/// Slash-command form fields as Slack and Discord send them, only `text`
+/// (everything after the command name) is used
+#[derive(Deserialize, Debug)]
+struct SlashCommandForm {
+    #[serde(default)]
+    text: String,
+}
+
+const SLASH_USAGE: &str = "Usage: `add <text>`, `done <id>`, `remove <id>`, `priority <id> <priority>` or `list [filter]`";
+
+/// Message body chat apps render as the command's reply. Only the one who
+/// ran the command sees an ephemeral reply.
+#[derive(Serialize)]
+struct SlashReply {
+    response_type: &'static str,
+    text: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
 struct UpdateTodo {
     text: Option<String>,
     done: Option<bool>,
+    #[serde(default, deserialize_with = "priority_by_name")]
+    priority: Option<Priority>,
+    due: Option<DateTime<Utc>>,
+    /// Removes the due date, `due` is ignored when this is set
+    #[serde(default)]
+    clear_due: bool,
+    /// `null` removes the dependency, leaving the field out keeps it
+    #[serde(default, deserialize_with = "present")]
+    depends_on: Option<Option<i64>>,
+    estimate_minutes: Option<i32>,
+    spent_minutes: Option<i32>,
+    /// An empty string clears the label
+    label: Option<String>,
+    /// The version the client last saw, the update is refused if the task
+    /// has changed since. Falls back to `If-Match` over REST.
+    version: Option<i64>,
+}
+
+/// Body of `PATCH /todos`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct BulkUpdate {
+    /// Limits the update to these tasks, those of them the filter still
+    /// matches. Every matching task when left out.
+    #[serde(default)]
+    ids: Option<Vec<i64>>,
+    changes: UpdateTodo,
+}
+
+/// Tells a field sent as `null`, `Some(None)`, apart from one left out, which
+/// `#[serde(default)]` makes `None`
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// `Json`, except a body that doesn't fit the type is a 400 carrying serde's
+/// explanation (which field, what type it expected) instead of axum's default
+struct ValidJson<T>(T);
+
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(AppError::InvalidBody)?;
+        Ok(ValidJson(value))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LogTime {
+    minutes: u32,
+}
+
+#[derive(Serialize)]
+struct TimeStats {
+    estimate_minutes: i64,
+    spent_minutes: i64,
+    total: i64,
+    done: i64,
+    /// Share of tasks that are done, 0 to 100
+    completion_pct: f64,
+    low: i64,
+    medium: i64,
+    high: i64,
+}
+
+/// `done` as a percentage of `total`, 0 for an empty list so the JSON never
+/// carries a NaN
+fn completion_pct(done: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        done as f64 / total as f64 * 100.0
+    }
+}
+
+/// Tasks split into one list per priority, each kept in fetch order
+#[derive(Serialize, Default)]
+struct GroupedTasks {
+    high: Vec<Task>,
+    medium: Vec<Task>,
+    low: Vec<Task>,
+}
+
+impl From<Vec<Task>> for GroupedTasks {
+    fn from(tasks: Vec<Task>) -> Self {
+        let mut grouped = GroupedTasks::default();
+        for task in tasks {
+            match task.priority {
+                Priority::High => grouped.high.push(task),
+                Priority::Medium => grouped.medium.push(task),
+                Priority::Low => grouped.low.push(task),
+            }
+        }
+        grouped
+    }
+}
+
+#[derive(Serialize)]
+struct CompletedCount {
+    completed: u64,
+}
+
+#[derive(Serialize)]
+struct ImportedCount {
+    imported: u64,
+}
+
+/// One `- [ ] (H) text` line of a Markdown checklist
+#[derive(Debug, PartialEq)]
+struct ChecklistItem {
+    text: String,
+    done: bool,
+    priority: Option<Priority>,
+}
+
+impl ChecklistItem {
+    /// Takes `-` or `*` bullets with `[ ]`, `[x]` or `[X]` boxes, and an
+    /// optional `(L)`/`(M)`/`(H)` right after the box. Anything else,
+    /// including an item with no text, is `None`.
+    fn parse(line: &str) -> Option<ChecklistItem> {
+        let rest = line.trim_start();
+        let rest = rest
+            .strip_prefix("- ")
+            .or_else(|| rest.strip_prefix("* "))?
+            .trim_start();
+        let (done, rest) = if let Some(rest) = rest.strip_prefix("[ ]") {
+            (false, rest)
+        } else {
+            let rest = rest
+                .strip_prefix("[x]")
+                .or_else(|| rest.strip_prefix("[X]"))?;
+            (true, rest)
+        };
+        let rest = rest.trim();
+        let (priority, text) = match rest.split_once(char::is_whitespace) {
+            Some((token, text)) if token.starts_with('(') => match token.parse() {
+                Ok(priority) => (Some(priority), text.trim()),
+                Err(_) => (None, rest),
+            },
+            _ => (None, rest),
+        };
+        if text.is_empty() {
+            return None;
+        }
+        Some(ChecklistItem {
+            text: text.to_string(),
+            done,
+            priority,
+        })
+    }
+}
+
+/// Writes the item back as a checklist line, always with its priority, in
+/// the form `parse` reads
+impl std::fmt::Display for ChecklistItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.done { "[x]" } else { "[ ]" };
+        let priority = self.priority.unwrap_or_default();
+        write!(f, "- {status} {priority} {}", self.text)
+    }
+}
+
+impl From<&Task> for ChecklistItem {
+    fn from(task: &Task) -> Self {
+        ChecklistItem {
+            text: task.text.clone(),
+            done: task.done,
+            priority: Some(task.priority),
+        }
+    }
+}
+
+/// A named set of tasks created together, e.g. a release checklist
+#[derive(Serialize, Debug)]
+struct Template {
+    id: i64,
+    name: String,
+    tasks: Vec<TemplateTask>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct NewTemplate {
+    name: String,
+    tasks: Vec<TemplateTask>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct TemplateTask {
+    text: String,
+    /// Left out to use the server's default priority when applied
+    #[serde(
+        default,
+        deserialize_with = "priority_by_name",
+        skip_serializing_if = "Option::is_none"
+    )]
     priority: Option<Priority>,
 }
 
+/// Just enough of a task for a shell completion script to offer its id
+#[derive(Serialize, Debug, PartialEq)]
+struct TaskId {
+    id: i64,
+    hint: String,
+}
+
+impl TaskId {
+    /// Words of the text kept as the hint, enough to tell tasks apart
+    const HINT_WORDS: usize = 3;
+
+    fn new(id: i64, text: &str) -> Self {
+        let hint = text
+            .split_whitespace()
+            .take(Self::HINT_WORDS)
+            .collect::<Vec<_>>()
+            .join(" ");
+        TaskId { id, hint }
+    }
+}
+
+#[derive(Serialize)]
+struct DeletedCount {
+    deleted: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct UpdatedCount {
+    updated: u64,
+}
+
+#[derive(Serialize)]
+struct RolloverCount {
+    moved: u64,
+}
+
+/// How `POST /todos/normalize-priority` rebalances priorities, picked by the
+/// body's `strategy` field. Only open tasks outside the trash are touched.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+enum Normalization {
+    /// One level down for tasks nobody has changed in `days`. Demoting counts
+    /// as a change, so a task drops again only after another `days`.
+    DemoteStale { days: u32 },
+    /// Every High task past the `keep` most recently changed drops to Medium
+    CapHigh { keep: u32 },
+}
+
+#[derive(Serialize)]
+struct NormalizedCount {
+    changed: u64,
+}
+
+#[derive(Serialize)]
+struct Backup {
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum BackupError {
+    #[error("failed to create backup directory")]
+    CreateDir(#[source] std::io::Error),
+    #[error("failed to write backup")]
+    Database(#[from] sqlx::Error),
+    #[error("failed to read backup size")]
+    Metadata(#[source] std::io::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+enum UpdateError {
+    #[error("task is blocked by an unfinished dependency")]
+    Blocked,
+    #[error("task with that id was not found")]
+    NotFound,
+    #[error("task was changed by someone else, refetch it and try again")]
+    Conflict,
+    #[error("a task can't depend on itself or on a task that depends on it")]
+    DependencyCycle,
+    #[error("dependency {0} was not found")]
+    MissingDependency(i64),
+    #[error("the list is limited to {0} tasks, finish or delete some first")]
+    LimitReached(u64),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+/// What a handler can fail with. Each turns into its status code and a
+/// `{"error": ...}` body, except internal errors, which are logged and
+/// reported without their details.
+#[derive(thiserror::Error, Debug)]
+enum AppError {
+    #[error("task with that id was not found")]
+    NotFound,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Forbidden(&'static str),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    LimitReached(String),
+    #[error("{}", .0.body_text())]
+    InvalidBody(JsonRejection),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Backup(#[from] BackupError),
+    #[error(transparent)]
+    Serialize(#[from] serde_json::Error),
+}
+
+impl From<UpdateError> for AppError {
+    fn from(e: UpdateError) -> Self {
+        match e {
+            UpdateError::Blocked | UpdateError::Conflict => AppError::Conflict(e.to_string()),
+            UpdateError::NotFound => AppError::NotFound,
+            UpdateError::DependencyCycle | UpdateError::MissingDependency(_) => {
+                AppError::BadRequest(e.to_string())
+            }
+            UpdateError::LimitReached(_) => AppError::LimitReached(e.to_string()),
+            UpdateError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::LimitReached(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::InvalidBody(
+                JsonRejection::JsonDataError(_) | JsonRejection::JsonSyntaxError(_),
+            ) => StatusCode::BAD_REQUEST,
+            // a missing content type or oversized body keeps its own status
+            AppError::InvalidBody(rejection) => rejection.status(),
+            AppError::Database(_) | AppError::Backup(_) | AppError::Serialize(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        let message = if status.is_server_error() {
+            error!("Request failed: {self}");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+/// Top-level JSON:API document, sent instead of the plain array when the
+/// client asks for `application/vnd.api+json`
+#[derive(Serialize)]
+struct JsonApiDocument {
+    data: Vec<JsonApiResource>,
+    /// Only there when the request was paged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<JsonApiMeta>,
+}
+
+#[derive(Serialize)]
+struct JsonApiMeta {
+    next_cursor: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct JsonApiResource {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+    attributes: serde_json::Value,
+}
+
+impl From<Vec<Task>> for JsonApiDocument {
+    fn from(tasks: Vec<Task>) -> Self {
+        let data = tasks
+            .into_iter()
+            .map(|task| {
+                let id = task.id.to_string();
+                // the id lives at the top level of a resource, not in attributes
+                let mut attributes = serde_json::to_value(task).unwrap_or_default();
+                if let Some(attributes) = attributes.as_object_mut() {
+                    attributes.remove("id");
+                }
+                JsonApiResource {
+                    kind: "task",
+                    id,
+                    attributes,
+                }
+            })
+            .collect();
+        JsonApiDocument { data, meta: None }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "todo_server=debug,tower_http=debug".into()),
+                .unwrap_or_else(|_| "todo_server=debug,todo_common=warn,tower_http=debug".into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    dotenvy::dotenv().unwrap();
-    let db_url = std::env::var("DATABASE_URL").unwrap();
-    let pool = SqlitePoolOptions::new().connect(&db_url).await.unwrap();
+    // no .env is fine as long as the variables are set some other way, but a
+    // .env that's there and broken should stop startup
+    match dotenvy::dotenv() {
+        Ok(_) => {}
+        Err(e) if e.not_found() => {}
+        Err(e) => exit_with_error(&format!("failed to load .env: {e}")),
+    }
+    let db_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| {
+        exit_with_error(
+            "DATABASE_URL is not set; create a .env with DATABASE_URL=sqlite:todos.db \
+            (see .env.example)",
+        )
+    });
+    // a fresh checkout just works by default, DATABASE_CREATE=0 makes a
+    // missing database file an error instead
+    let create_if_missing = !std::env::var("DATABASE_CREATE")
+        .is_ok_and(|value| matches!(value.as_str(), "0" | "false"));
+    let connect_options = SqliteConnectOptions::from_str(&db_url)
+        .unwrap_or_else(|e| exit_with_error(&format!("invalid DATABASE_URL {db_url:?}: {e}")))
+        .create_if_missing(create_if_missing);
+    // the database may still be coming up when started alongside it
+    let connect_attempts = match std::env::var("DATABASE_CONNECT_ATTEMPTS") {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&attempts| attempts > 0)
+            .unwrap_or_else(|| {
+                exit_with_error(&format!(
+                    "DATABASE_CONNECT_ATTEMPTS={value:?} is not a positive count"
+                ))
+            }),
+        _ => DEFAULT_CONNECT_ATTEMPTS,
+    };
+    let pool = connect_with_backoff(connect_options, connect_attempts)
+        .await
+        .unwrap_or_else(|e| exit_with_error(&format!("failed to open {db_url}: {e}")));
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .unwrap_or_else(|e| exit_with_error(&format!("failed to run migrations: {e}")));
+    let search_index = match setup_fts(&pool).await {
+        Ok(()) => SearchIndex::Fts,
+        Err(e) => {
+            warn!("Full-text search unavailable, searching with LIKE instead: {e}");
+            SearchIndex::Like
+        }
+    };
+
+    let metrics = PrometheusBuilder::new().install_recorder().unwrap();
+
+    // a typo here would silently file every task under the wrong priority,
+    // so refuse to start instead
+    let default_priority = match std::env::var("DEFAULT_PRIORITY") {
+        Ok(value) if !value.trim().is_empty() => value.parse().unwrap_or_else(|e| {
+            exit_with_error(&format!(
+                "DEFAULT_PRIORITY={value:?} is not a valid priority: {e}"
+            ))
+        }),
+        _ => Priority::default(),
+    };
+    info!("New tasks default to {default_priority:?} priority");
+
+    let max_tasks = match std::env::var("MAX_TASKS") {
+        Ok(value) if !value.trim().is_empty() => Some(value.trim().parse().unwrap_or_else(|e| {
+            exit_with_error(&format!("MAX_TASKS={value:?} is not a valid count: {e}"))
+        })),
+        _ => None,
+    };
+    if let Some(max) = max_tasks {
+        info!("Lists are capped at {max} tasks");
+    }
+
+    // same rule as DEV_MODE, only the exact value 1 counts
+    let read_only = std::env::var("READ_ONLY").is_ok_and(|value| value == "1");
+    if read_only {
+        info!("READ_ONLY is set, requests that change tasks will be refused");
+    }
+
+    let schema = graphql::schema(
+        pool.clone(),
+        default_priority,
+        search_index,
+        read_only,
+        max_tasks,
+    );
+
+    // only the exact value 1 turns dev mode on, so a stray DEV_MODE=0 or
+    // DEV_MODE=false can't expose the reset endpoint
+    let dev_mode = std::env::var("DEV_MODE").is_ok_and(|value| value == "1");
+    if dev_mode {
+        info!("DEV_MODE is set, POST /todos/reset and POST /admin/backup are enabled");
+    }
+
+    let backup_dir = std::env::var("BACKUP_DIR").map_or_else(|_| "backups".into(), PathBuf::from);
+    // periodic backups are opt-in, unset means only on POST /admin/backup
+    let backup_interval = std::env::var("BACKUP_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
+    if let Some(interval) = backup_interval {
+        let pool = pool.clone();
+        let backup_dir = backup_dir.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // the first tick fires straight away, skip it so startup doesn't
+            // always write a backup
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match write_backup(&pool, &backup_dir).await {
+                    Ok(backup) => info!("Scheduled backup written to {:?}", backup.path),
+                    Err(e) => error!("Scheduled backup failed: {e}"),
+                }
+            }
+        });
+    }
 
-    let state = Arc::new(AppState { pool });
+    let state = Arc::new(AppState {
+        pool,
+        metrics,
+        dev_mode,
+        default_priority,
+        backup_dir,
+        search_index,
+        max_tasks,
+        read_only,
+    });
     let app = Router::new()
-        .route("/todos", get(fetch_todos).post(add_todo))
+        .route("/graphql", post_service(GraphQL::new(schema)))
+        .route(
+            "/todos",
+            get(fetch_todos)
+                .post(add_todo)
+                .patch(update_matching)
+                .delete(delete_matching),
+        )
+        .route("/todos/stream", get(stream_todos))
+        .route("/todos/email", post(add_todo_from_email))
+        .route("/todos/import-markdown", post(import_markdown))
+        .route("/todos/export-markdown", get(export_markdown))
+        .route("/todos/webhook", post(slash_command))
+        .route("/todos/grouped", get(grouped_todos))
+        .route("/todos/ids", get(task_ids))
+        .route("/todos/changes", get(fetch_changes))
+        .route("/todos/completed-today", get(completed_today))
+        .route("/todos/stats", get(time_stats))
+        .route("/todos/trash", get(fetch_trash))
+        .route("/todos/reset", post(reset_todos))
+        .route("/todos/complete-all", post(complete_all))
+        .route("/todos/rollover", post(rollover))
+        .route("/todos/normalize-priority", post(normalize_priority))
+        .route("/todos/{id}/log-time", post(log_time))
+        .route("/todos/{id}/duplicate", post(duplicate_task))
+        .route("/todos/{id}/toggle", post(toggle_task))
+        .route("/todos/{id}/restore", post(restore))
+        .route("/todos/{id}/purge", delete(purge))
         .route("/todos/{id}", patch(update_task))
         .route("/todos/{id}", delete(delete_task))
+        .route("/templates", get(fetch_templates).post(add_template))
+        .route("/templates/{id}/apply", post(apply_template))
+        .route("/admin/backup", post(backup))
+        .route("/metrics", get(render_metrics))
+        .route_layer(middleware::from_fn_with_state(state.clone(), refuse_writes))
         .with_state(state)
+        // bodies over the limit are rejected with 413, and handlers that take
+        // too long are cut off with 408
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_timeout))
+                .layer(TimeoutLayer::new(REQUEST_TIMEOUT))
+                .layer(RequestBodyLimitLayer::new(MAX_BODY_BYTES)),
+        )
+        .layer(middleware::map_response(count_errors))
         .layer(TraceLayer::new_for_http());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
 
+/// For setup mistakes a new user can fix, where a panic's backtrace would
+/// only get in the way
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("error: {message}");
+    std::process::exit(1);
+}
+
 #[instrument(skip(state))]
 async fn fetch_todos(
     State(state): State<Arc<AppState>>,
     Query(params): Query<TaskQuery>,
-) -> Json<Vec<Task>> {
-    let mut query = QueryBuilder::new("SELECT id, text, done, priority FROM tasks");
+    Query(debug_params): Query<DebugParams>,
+    Query(urgency_params): Query<UrgencyParams>,
+    Query(pretty_params): Query<PrettyParams>,
+    request_headers: HeaderMap,
+) -> Result<Response, AppError> {
+    counter!("todo_requests_total", "route" => "fetch_todos").increment(1);
+    check_limit(params.limit)?;
+    let mut rows =
+        select_tasks(&state.pool, &params, state.search_index, debug_params.debug).await?;
 
-    let mut has_where = false;
+    let mut headers = HeaderMap::new();
+    let mut next_cursor = None;
+    if let Some(limit) = params.limit
+        && rows.len() as i64 > limit
+    {
+        rows.truncate(limit as usize);
+        next_cursor = rows.last().map(|last| last.id);
+    }
+    if let Some(cursor) = next_cursor {
+        headers.insert("x-next-cursor", HeaderValue::from(cursor));
+    }
 
-    if let Some(done) = params.done {
-        query.push(" WHERE done = ");
-        query.push_bind(done);
-        has_where = true;
+    // worked out here against the server's clock so every client agrees on
+    // what's overdue
+    if urgency_params.with_urgency {
+        let now = Utc::now();
+        for task in &mut rows {
+            task.urgency = Some(Urgency::of(task, now));
+        }
     }
 
-    if let Some(priority) = params.priority {
-        if has_where {
-            query.push(" AND ");
-        } else {
-            query.push(" WHERE ");
+    info!("Fetching filtered todos");
+    let pretty = pretty_params.pretty;
+    let (content_type, body) = if wants_json_api(&request_headers) {
+        let mut document = JsonApiDocument::from(rows);
+        if params.limit.is_some() {
+            document.meta = Some(JsonApiMeta { next_cursor });
         }
-        query.push("priority = ");
-        query.push_bind(priority);
+        (JSON_API_MEDIA_TYPE, json_bytes(&document, pretty)?)
+    } else if params.limit.is_some() {
+        let page = TaskPage {
+            tasks: rows,
+            next_cursor,
+        };
+        ("application/json", json_bytes(&page, pretty)?)
+    } else {
+        ("application/json", json_bytes(&rows, pretty)?)
+    };
+
+    // the ETag is a hash of the exact body, so it changes whenever anything
+    // in the response would
+    let etag = body_etag(&body);
+    headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+    if etag_matches(&request_headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
     }
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Ok((headers, body).into_response())
+}
 
-    // let query = query.build().sql();
+/// A page needs room for at least one task, or there'd be no last task to
+/// take the next cursor from. SQLite would also read a negative `LIMIT` as
+/// no limit at all.
+fn check_limit(limit: Option<i64>) -> Result<(), AppError> {
+    match limit {
+        Some(limit) if limit < 1 => Err(AppError::BadRequest(format!(
+            "limit must be at least 1, got {limit}"
+        ))),
+        _ => Ok(()),
+    }
+}
 
-    let rows = query
-        .build_query_as::<Task>()
-        .fetch_all(&state.pool)
-        .await
-        .unwrap();
+/// The same filtered list as `GET /todos`, split up by priority. Every
+/// matching task is returned, paging params are ignored.
+#[instrument(skip(state))]
+async fn grouped_todos(
+    State(state): State<Arc<AppState>>,
+    Query(mut params): Query<TaskQuery>,
+    Query(pretty_params): Query<PrettyParams>,
+) -> Result<MaybePretty<GroupedTasks>, AppError> {
+    counter!("todo_requests_total", "route" => "grouped_todos").increment(1);
+    info!("Fetching todos grouped by priority");
+    params.limit = None;
+    params.after_id = None;
+    let rows = select_tasks(&state.pool, &params, state.search_index, false).await?;
+    Ok(MaybePretty {
+        value: GroupedTasks::from(rows),
+        pretty: pretty_params.pretty,
+    })
+}
 
-    info!("Fetching filtered todos");
-    Json(rows)
+/// Streams every task as newline-delimited JSON, one row at a time, so memory
+/// use doesn't grow with the size of the list.
+#[instrument(skip(state))]
+async fn stream_todos(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    counter!("todo_requests_total", "route" => "stream_todos").increment(1);
+    info!("Streaming todos");
+
+    let lines = async_stream::stream! {
+        let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE deleted_at IS NULL ORDER BY id");
+        let mut rows = sqlx::query_as::<_, Task>(&sql).fetch(&state.pool);
+        while let Some(row) = rows.next().await {
+            yield row
+                .map_err(BoxError::from)
+                .and_then(|task| {
+                    let mut line = serde_json::to_vec(&task)?;
+                    line.push(b'\n');
+                    Ok(line)
+                });
+        }
+    };
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
 }
 
+/// Ids with the first few words of each task, taking the same filters as
+/// `GET /todos`, for shell completion of commands like `todo done <TAB>`
 #[instrument(skip(state))]
-async fn add_todo(State(state): State<Arc<AppState>>, Json(payload): Json<CreateTodo>) {
-    let sql = "INSERT INTO tasks (text, done, priority) values ($1, false, 'Low')";
-    info!("Adding task to database: {}", payload.text);
-    sqlx::query(sql)
-        .bind(payload.text)
-        .execute(&state.pool)
-        .await
-        .unwrap();
+async fn task_ids(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TaskQuery>,
+) -> Result<Json<Vec<TaskId>>, AppError> {
+    counter!("todo_requests_total", "route" => "task_ids").increment(1);
+    let mut query = QueryBuilder::new("SELECT id, text FROM tasks");
+    apply_task_query(&mut query, &params, state.search_index);
+    query.push(" ORDER BY id");
+    let rows: Vec<(i64, String)> = query.build_query_as().fetch_all(&state.pool).await?;
+    Ok(Json(
+        rows.iter()
+            .map(|(id, text)| TaskId::new(*id, text))
+            .collect(),
+    ))
 }
 
 #[instrument(skip(state))]
-async fn delete_task(State(state): State<Arc<AppState>>, Path(id): Path<i64>) {
-    info!("Deleting task ID: {}", id);
-    sqlx::query!("DELETE FROM tasks WHERE id = $1", id)
-        .execute(&state.pool)
-        .await
-        .unwrap();
+async fn fetch_templates(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Template>>, AppError> {
+    counter!("todo_requests_total", "route" => "fetch_templates").increment(1);
+    Ok(Json(select_templates(&state.pool).await?))
 }
 
+/// Saves a template. Names are unique, reusing one is a 409.
 #[instrument(skip(state))]
-async fn update_task(
+async fn add_template(
+    State(state): State<Arc<AppState>>,
+    ValidJson(payload): ValidJson<NewTemplate>,
+) -> Result<Json<Template>, AppError> {
+    counter!("todo_requests_total", "route" => "add_template").increment(1);
+    let name = payload.name.trim();
+    if name.is_empty() || payload.tasks.is_empty() {
+        return Err(AppError::BadRequest(
+            "a template needs a name and at least one task".to_string(),
+        ));
+    }
+    if payload
+        .tasks
+        .iter()
+        .any(|task| parse_quick_add(&task.text).0.is_empty())
+    {
+        return Err(AppError::BadRequest(
+            "every template task needs some text".to_string(),
+        ));
+    }
+    info!(
+        "Saving template {name:?} with {} tasks",
+        payload.tasks.len()
+    );
+    match insert_template(&state.pool, name, payload.tasks).await {
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(AppError::Conflict(
+            format!("a template named {name:?} already exists"),
+        )),
+        result => Ok(Json(result?)),
+    }
+}
+
+/// Creates every task of the template, all or none
+#[instrument(skip(state))]
+async fn apply_template(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-    Json(payload): Json<UpdateTodo>,
-) {
-    info!("Updating task ID: {} with {:?}", id, payload);
-    // COALESCE returns first non null expression
-    // so either value from payload, or the value that's already set
-    sqlx::query!(
-        "UPDATE tasks SET text = COALESCE($1, text), done = COALESCE($2, done), priority = COALESCE($3, priority) WHERE id = $4",
-        payload.text,
-        payload.done,
-        payload.priority,
-        id
+) -> Result<Json<Vec<Task>>, AppError> {
+    counter!("todo_requests_total", "route" => "apply_template").increment(1);
+    let template = select_templates(&state.pool)
+        .await?
+        .into_iter()
+        .find(|t| t.id == id)
+        .ok_or(AppError::NotFound)?;
+    info!("Applying template {:?}", template.name);
+    let tasks = insert_template_tasks(
+        &state.pool,
+        &template,
+        state.default_priority,
+        state.max_tasks,
     )
-    .execute(&state.pool)
-    .await
-    .unwrap();
+    .await?;
+    Ok(Json(tasks))
+}
+
+/// Tasks marked done since local midnight
+#[instrument(skip(state))]
+async fn completed_today(
+    State(state): State<Arc<AppState>>,
+    Query(pretty_params): Query<PrettyParams>,
+) -> Result<MaybePretty<Vec<Task>>, AppError> {
+    counter!("todo_requests_total", "route" => "completed_today").increment(1);
+    let start_of_day = Local::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map(|midnight| midnight.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+
+    info!("Fetching tasks completed since {start_of_day}");
+    let sql = format!(
+        "SELECT {TASK_COLUMNS} FROM tasks WHERE done = true AND completed_at >= $1 AND deleted_at IS NULL
+        ORDER BY completed_at"
+    );
+    let rows = sqlx::query_as::<_, Task>(&sql)
+        .bind(start_of_day)
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(MaybePretty {
+        value: rows,
+        pretty: pretty_params.pretty,
+    })
+}
+
+/// Adds to a task's spent time in a single statement, so concurrent logs
+/// don't overwrite each other
+#[instrument(skip(state))]
+async fn log_time(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<LogTime>,
+) -> Result<Json<Task>, AppError> {
+    counter!("todo_requests_total", "route" => "log_time").increment(1);
+    info!("Logging {} minutes on task ID: {}", payload.minutes, id);
+    let sql = format!(
+        "UPDATE tasks SET spent_minutes = COALESCE(spent_minutes, 0) + $1, version = version + 1
+        WHERE id = $2 AND deleted_at IS NULL RETURNING {TASK_COLUMNS}"
+    );
+    sqlx::query_as::<_, Task>(&sql)
+        .bind(payload.minutes)
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .map(Json)
+        .ok_or(AppError::NotFound)
+}
+
+/// Flips a task between done and not done in one statement, so two clients
+/// toggling at once can't both act on a stale `done`
+#[instrument(skip(state))]
+async fn toggle_task(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Task>, AppError> {
+    counter!("todo_requests_total", "route" => "toggle_task").increment(1);
+    info!("Toggling task ID: {}", id);
+    Ok(Json(flip_done(&state.pool, id).await?))
+}
+
+/// Creates a task from an email forwarded by a mail gateway, see
+/// `EmailTodo::into_task` for how it's mapped
+#[instrument(skip(state))]
+async fn add_todo_from_email(
+    State(state): State<Arc<AppState>>,
+    ValidJson(email): ValidJson<EmailTodo>,
+) -> Result<Json<Task>, AppError> {
+    counter!("todo_requests_total", "route" => "add_todo_from_email").increment(1);
+    let payload = email
+        .into_task()
+        .ok_or_else(|| AppError::BadRequest("the email has no subject".to_string()))?;
+    info!("Adding task from email: {}", payload.text);
+    Ok(Json(
+        insert_task(
+            &state.pool,
+            payload,
+            state.default_priority,
+            state.max_tasks,
+        )
+        .await?,
+    ))
+}
+
+/// Runs a chat slash command (see `parse_command`) against the list and
+/// answers with a message for the chat app to show. Requests aren't
+/// signature checked yet.
+#[instrument(skip(state))]
+async fn slash_command(
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<SlashCommandForm>,
+) -> Result<Json<SlashReply>, AppError> {
+    counter!("todo_requests_total", "route" => "slash_command").increment(1);
+    info!("Running slash command: {}", form.text);
+    let command = match parse_command(&form.text) {
+        Ok(command) => command,
+        Err(e) => {
+            return Ok(Json(SlashReply {
+                response_type: "ephemeral",
+                text: format!("{e}. {SLASH_USAGE}"),
+            }));
+        }
+    };
+    if state.read_only && !matches!(command, Command::List(_)) {
+        return Ok(Json(SlashReply {
+            response_type: "ephemeral",
+            text: READ_ONLY_MESSAGE.to_string(),
+        }));
+    }
+    let (response_type, text) = match command {
+        Command::Add(text) => {
+            let payload = CreateTodo {
+                text,
+                priority: None,
+                due: None,
+                depends_on: None,
+                source: Some("webhook".to_string()),
+                description: None,
+                tags: Vec::new(),
+            };
+            match insert_task(
+                &state.pool,
+                payload,
+                state.default_priority,
+                state.max_tasks,
+            )
+            .await
+            {
+                Ok(task) => ("in_channel", format!("Added {task}")),
+                Err(e @ UpdateError::LimitReached(_)) => ("ephemeral", e.to_string()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Command::List(filter) => {
+            let params = TaskQuery::from(filter);
+            let tasks = select_tasks(&state.pool, &params, state.search_index, false).await?;
+            let text = if tasks.is_empty() {
+                "No matching tasks".to_string()
+            } else {
+                tasks
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            ("ephemeral", text)
+        }
+        Command::Remove(id) => {
+            if remove_task(&state.pool, id).await? {
+                ("in_channel", format!("Moved task {id} to the trash"))
+            } else {
+                ("ephemeral", format!("No task with id {id}"))
+            }
+        }
+        Command::Done(id) => {
+            let payload = UpdateTodo {
+                done: Some(true),
+                ..Default::default()
+            };
+            update_reply(id, apply_update(&state.pool, id, payload).await)?
+        }
+        Command::SetPriority(id, priority) => {
+            let payload = UpdateTodo {
+                priority: Some(priority),
+                ..Default::default()
+            };
+            update_reply(id, apply_update(&state.pool, id, payload).await)?
+        }
+    };
+    Ok(Json(SlashReply {
+        response_type,
+        text,
+    }))
+}
+
+/// Reply type and message for a slash command that updated task `id`
+fn update_reply(
+    id: i64,
+    result: Result<bool, UpdateError>,
+) -> Result<(&'static str, String), AppError> {
+    match result {
+        Ok(true) => Ok(("in_channel", format!("Updated task {id}"))),
+        Ok(false) => Ok(("ephemeral", format!("No task with id {id}"))),
+        Err(UpdateError::Blocked) => Ok((
+            "ephemeral",
+            format!("Task {id} is blocked by an unfinished dependency"),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Copies a task's text and priority into a new, not yet done task
+#[instrument(skip(state))]
+async fn duplicate_task(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Task>, AppError> {
+    counter!("todo_requests_total", "route" => "duplicate_task").increment(1);
+    info!("Duplicating task ID: {}", id);
+    let source = sqlx::query!(
+        r#"SELECT text, priority AS "priority: Priority" FROM tasks
+        WHERE id = $1 AND deleted_at IS NULL"#,
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    let payload = CreateTodo {
+        text: source.text,
+        priority: Some(source.priority),
+        due: None,
+        depends_on: None,
+        source: None,
+        description: None,
+        tags: Vec::new(),
+    };
+    Ok(Json(
+        insert_task(
+            &state.pool,
+            payload,
+            state.default_priority,
+            state.max_tasks,
+        )
+        .await?,
+    ))
+}
+
+/// Estimated and spent minutes summed over every task, and how many are done
+#[instrument(skip(state))]
+async fn time_stats(
+    State(state): State<Arc<AppState>>,
+    Query(pretty_params): Query<PrettyParams>,
+) -> Result<MaybePretty<TimeStats>, AppError> {
+    counter!("todo_requests_total", "route" => "time_stats").increment(1);
+    let row = sqlx::query!(
+        r#"SELECT COALESCE(SUM(estimate_minutes), 0) AS "estimate_minutes!: i64",
+        COALESCE(SUM(spent_minutes), 0) AS "spent_minutes!: i64",
+        COUNT(*) AS "total!: i64", COALESCE(SUM(done), 0) AS "done!: i64",
+        COALESCE(SUM(lower(priority) = 'high'), 0) AS "high!: i64",
+        COALESCE(SUM(lower(priority) = 'medium'), 0) AS "medium!: i64"
+        FROM tasks WHERE deleted_at IS NULL"#
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    let stats = TimeStats {
+        estimate_minutes: row.estimate_minutes,
+        spent_minutes: row.spent_minutes,
+        total: row.total,
+        done: row.done,
+        completion_pct: completion_pct(row.done, row.total),
+        // anything unrecognised is read back as Low, so count it as Low too
+        low: row.total - row.high - row.medium,
+        medium: row.medium,
+        high: row.high,
+    };
+    Ok(MaybePretty {
+        value: stats,
+        pretty: pretty_params.pretty,
+    })
+}
+
+#[instrument(skip(state))]
+async fn add_todo(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ValidJson(mut payload): ValidJson<CreateTodo>,
+) -> Result<(), AppError> {
+    counter!("todo_requests_total", "route" => "add_todo").increment(1);
+    if payload.source.is_none() {
+        payload.source = request_source(&headers);
+    }
+    payload.parse_quick_add();
+    info!("Adding task to database: {}", payload.text);
+    insert_task(
+        &state.pool,
+        payload,
+        state.default_priority,
+        state.max_tasks,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Fails with `LimitReached` when more tasks than `MAX_TASKS` are outside the
+/// trash. Called after inserting, in the same transaction: the insert takes
+/// SQLite's write lock, so concurrent creates are counted one at a time and
+/// can't all slip under the cap.
+async fn check_task_limit(
+    tx: &mut SqliteConnection,
+    max_tasks: Option<u64>,
+) -> Result<(), UpdateError> {
+    let Some(max) = max_tasks else {
+        return Ok(());
+    };
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM tasks WHERE deleted_at IS NULL"#
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if count as u64 > max {
+        return Err(UpdateError::LimitReached(max));
+    }
+    Ok(())
+}
+
+/// Moves every task matching the filter to the trash in one statement. A
+/// request with no filter at all would empty the list, so it also needs
+/// `confirm=true`.
+#[instrument(skip(state))]
+async fn delete_matching(
+    State(state): State<Arc<AppState>>,
+    Query(mut params): Query<TaskQuery>,
+    Query(confirm_params): Query<ConfirmParams>,
+) -> Result<Json<DeletedCount>, AppError> {
+    counter!("todo_requests_total", "route" => "delete_matching").increment(1);
+    refuse_paging(&params)?;
+    // `?search=` would match every task
+    params.search = params.search.filter(|search| !search.trim().is_empty());
+    if !narrows_deletion(&params) && !confirm_params.confirm {
+        info!("Refusing to delete every task without confirm=true");
+        return Err(AppError::BadRequest(
+            "deleting every task needs confirm=true".to_string(),
+        ));
+    }
+    info!("Deleting all tasks matching {:?}", params);
+    let deleted = remove_tasks(&state.pool, &params, state.search_index).await?;
+    Ok(Json(DeletedCount { deleted }))
+}
+
+/// Applies the same changes to every task matching the filter, or to the
+/// listed ones among them, all or none. One blocked or missing dependency
+/// fails the whole batch. `version` is refused since it can only describe a
+/// single task.
+#[instrument(skip(state))]
+async fn update_matching(
+    State(state): State<Arc<AppState>>,
+    Query(mut params): Query<TaskQuery>,
+    ValidJson(bulk): ValidJson<BulkUpdate>,
+) -> Result<Json<UpdatedCount>, AppError> {
+    counter!("todo_requests_total", "route" => "update_matching").increment(1);
+    refuse_paging(&params)?;
+    if bulk.changes.version.is_some() {
+        return Err(AppError::BadRequest(
+            "version can't be used when updating several tasks".to_string(),
+        ));
+    }
+    params.search = params.search.filter(|search| !search.trim().is_empty());
+    info!("Updating all tasks matching {:?} with {:?}", params, bulk);
+    let updated = update_tasks(
+        &state.pool,
+        &params,
+        state.search_index,
+        bulk.ids.as_deref(),
+        &bulk.changes,
+    )
+    .await?;
+    Ok(Json(UpdatedCount { updated }))
+}
+
+/// Whether the filter leaves out any task by what it is
+fn narrows_deletion(params: &TaskQuery) -> bool {
+    params.done.is_some() || params.priority.is_some() || params.search.is_some()
+}
+
+/// Routes that change every matching task at once can't honour a page, so
+/// `limit` or `after_id` is refused rather than silently ignored
+fn refuse_paging(params: &TaskQuery) -> Result<(), AppError> {
+    if params.limit.is_some() || params.after_id.is_some() {
+        return Err(AppError::BadRequest(
+            "limit and after_id can't be used here, every matching task is changed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Tasks changed at or after `since`, for clients keeping a local copy in
+/// sync. The boundary is inclusive, so a change made in the same millisecond
+/// as the previous `as_of` is sent again rather than missed.
+#[instrument(skip(state))]
+async fn fetch_changes(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ChangesParams>,
+) -> Result<Json<Changes>, AppError> {
+    counter!("todo_requests_total", "route" => "fetch_changes").increment(1);
+    info!("Fetching changes since {}", params.since);
+    Ok(Json(select_changes(&state.pool, params.since).await?))
+}
+
+/// Soft-deleted tasks, most recently deleted first
+#[instrument(skip(state))]
+async fn fetch_trash(
+    State(state): State<Arc<AppState>>,
+    Query(pretty_params): Query<PrettyParams>,
+) -> Result<MaybePretty<Vec<Task>>, AppError> {
+    counter!("todo_requests_total", "route" => "fetch_trash").increment(1);
+    info!("Fetching trash");
+    let sql = format!(
+        "SELECT {TASK_COLUMNS} FROM tasks WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+    );
+    let rows = sqlx::query_as::<_, Task>(&sql)
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(MaybePretty {
+        value: rows,
+        pretty: pretty_params.pretty,
+    })
+}
+
+#[instrument(skip(state))]
+async fn restore(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Result<(), AppError> {
+    counter!("todo_requests_total", "route" => "restore").increment(1);
+    info!("Restoring task ID: {}", id);
+    if restore_task(&state.pool, id, state.max_tasks).await? {
+        Ok(())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+#[instrument(skip(state))]
+async fn purge(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    counter!("todo_requests_total", "route" => "purge").increment(1);
+    info!("Purging task ID: {}", id);
+    if purge_task(&state.pool, id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+#[instrument(skip(state))]
+async fn delete_task(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<(), AppError> {
+    counter!("todo_requests_total", "route" => "delete_task").increment(1);
+    info!("Deleting task ID: {}", id);
+    if remove_task(&state.pool, id).await? {
+        Ok(())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Marks every task matching the filter in the body done, or every task when
+/// there's no body. Tasks still blocked by a dependency are left alone.
+#[instrument(skip(state))]
+async fn complete_all(
+    State(state): State<Arc<AppState>>,
+    params: Option<Json<TaskQuery>>,
+) -> Result<Json<CompletedCount>, AppError> {
+    counter!("todo_requests_total", "route" => "complete_all").increment(1);
+    let params = params.map(|Json(params)| params).unwrap_or_default();
+    info!("Completing all tasks matching {:?}", params);
+    let completed = complete_tasks(&state.pool, &params, state.search_index).await?;
+    Ok(Json(CompletedCount { completed }))
+}
+
+/// Creates a task for every checklist line of a Markdown body, all or none,
+/// and returns how many there were. Other lines are skipped.
+#[instrument(skip(state, body))]
+async fn import_markdown(
+    State(state): State<Arc<AppState>>,
+    body: String,
+) -> Result<Json<ImportedCount>, AppError> {
+    counter!("todo_requests_total", "route" => "import_markdown").increment(1);
+    let items: Vec<_> = body.lines().filter_map(ChecklistItem::parse).collect();
+    info!("Importing {} tasks from Markdown", items.len());
+    let imported =
+        import_tasks(&state.pool, &items, state.default_priority, state.max_tasks).await?;
+    Ok(Json(ImportedCount { imported }))
+}
+
+/// Every task outside the trash as a Markdown checklist, one line per task
+/// in the form `import-markdown` reads back
+#[instrument(skip(state))]
+async fn export_markdown(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    counter!("todo_requests_total", "route" => "export_markdown").increment(1);
+    let tasks = select_tasks(
+        &state.pool,
+        &TaskQuery::default(),
+        state.search_index,
+        false,
+    )
+    .await?;
+    info!("Exporting {} tasks as Markdown", tasks.len());
+    let body: String = tasks
+        .iter()
+        .map(|task| format!("{}\n", ChecklistItem::from(task)))
+        .collect();
+    Ok((
+        [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+        body,
+    )
+        .into_response())
+}
+
+/// Carries overdue, unfinished tasks forward so they're due again today or
+/// tomorrow. Meant to be called nightly by something like cron.
+#[instrument(skip(state))]
+async fn rollover(State(state): State<Arc<AppState>>) -> Result<Json<RolloverCount>, AppError> {
+    counter!("todo_requests_total", "route" => "rollover").increment(1);
+    let moved = roll_over_overdue(&state.pool, Utc::now()).await?;
+    info!("Rolled over {moved} overdue tasks");
+    Ok(Json(RolloverCount { moved }))
+}
+
+/// Rebalances priorities with the strategy in the body, all or none, and
+/// returns how many tasks changed
+#[instrument(skip(state))]
+async fn normalize_priority(
+    State(state): State<Arc<AppState>>,
+    ValidJson(strategy): ValidJson<Normalization>,
+) -> Result<Json<NormalizedCount>, AppError> {
+    counter!("todo_requests_total", "route" => "normalize_priority").increment(1);
+    let changed = normalize_priorities(&state.pool, &strategy, Utc::now()).await?;
+    info!("Normalized priority of {changed} tasks with {:?}", strategy);
+    Ok(Json(NormalizedCount { changed }))
+}
+
+/// Wipes every task so test suites can start from a clean slate. Refused
+/// with 403 unless the server was started with `DEV_MODE=1`
+#[instrument(skip(state))]
+async fn reset_todos(State(state): State<Arc<AppState>>) -> Result<StatusCode, AppError> {
+    counter!("todo_requests_total", "route" => "reset_todos").increment(1);
+    if !state.dev_mode {
+        info!("Refusing reset, DEV_MODE is not enabled");
+        return Err(AppError::Forbidden("reset needs DEV_MODE=1"));
+    }
+    info!("Resetting database");
+    clear_tasks(&state.pool).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(skip(state))]
+async fn update_task(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    ValidJson(mut payload): ValidJson<UpdateTodo>,
+) -> Result<(), AppError> {
+    counter!("todo_requests_total", "route" => "update_task").increment(1);
+    if payload.version.is_none() {
+        payload.version = if_match_version(&headers);
+    }
+    info!("Updating task ID: {} with {:?}", id, payload);
+    if apply_update(&state.pool, id, payload).await? {
+        Ok(())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// Runs the filtered task query shared by the REST and GraphQL endpoints.
+/// With a `limit` set, one extra row is fetched so callers can tell whether
+/// there's another page.
+async fn select_tasks(
+    pool: &SqlitePool,
+    params: &TaskQuery,
+    search_index: SearchIndex,
+    debug: bool,
+) -> Result<Vec<Task>, sqlx::Error> {
+    let mut query = QueryBuilder::new(format!("SELECT {TASK_COLUMNS} FROM tasks"));
+
+    let mut bind_count = apply_task_query(&mut query, params, search_index);
+
+    // SQLite makes no promise about row order otherwise, and the list
+    // reshuffling after an edit is jarring
+    query.push(" ORDER BY id");
+
+    // fetch one extra row so we know whether there's another page
+    if let Some(limit) = params.limit {
+        query.push(" LIMIT ");
+        query.push_bind(limit.saturating_add(1));
+        bind_count += 1;
+    }
+
+    // only log the statement and how many values are bound, never the values
+    // themselves, so task text can't leak into the logs
+    if debug {
+        debug!(sql = query.sql(), binds = bind_count, "fetch_todos query");
+    }
+
+    query.build_query_as::<Task>().fetch_all(pool).await
+}
+
+/// Appends the WHERE clause for a task filter, returning how many values
+/// were bound. Soft-deleted tasks are always left out, and paging order and
+/// limit are left to the caller.
+fn apply_task_query(
+    query: &mut QueryBuilder<'_, sqlx::Sqlite>,
+    params: &TaskQuery,
+    search_index: SearchIndex,
+) -> usize {
+    let mut bind_count = 0;
+    query.push(" WHERE deleted_at IS NULL");
+
+    if let Some(done) = params.done {
+        query.push(" AND done = ");
+        query.push_bind(done);
+        bind_count += 1;
+    }
+
+    if let Some(priority) = params.priority {
+        query.push(" AND priority = ");
+        query.push_bind(priority);
+        bind_count += 1;
+    }
+
+    let match_mode = params.match_mode.unwrap_or_default();
+    let fts_query = params
+        .search
+        .as_deref()
+        .filter(|_| search_index == SearchIndex::Fts && match_mode != MatchMode::Exact)
+        .and_then(fts_query);
+    if let Some(fts_query) = fts_query {
+        // FTS matches whole words, so substring search finds words starting
+        // with each search term rather than the text anywhere in a word
+        query.push(" AND id IN (SELECT rowid FROM tasks_fts WHERE tasks_fts MATCH ");
+        query.push_bind(fts_query);
+        query.push(")");
+        bind_count += 1;
+    } else if let Some(search) = &params.search {
+        // escape LIKE wildcards so only the ones added for the match mode apply
+        let pattern = search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = match match_mode {
+            MatchMode::Substring => format!("%{pattern}%"),
+            MatchMode::Prefix => format!("{pattern}%"),
+            MatchMode::Exact => pattern,
+        };
+        query.push(" AND text LIKE ");
+        query.push_bind(pattern);
+        query.push(" ESCAPE '\\'");
+        bind_count += 1;
+    }
+
+    if let Some(after_id) = params.after_id {
+        query.push(" AND id > ");
+        query.push_bind(after_id);
+        bind_count += 1;
+    }
+
+    bind_count
+}
+
+/// Turns a search into an FTS5 query matching every word as a prefix, each
+/// quoted so FTS syntax like `OR` or `-` in it is taken literally. `None`
+/// when there are no words to search for.
+fn fts_query(search: &str) -> Option<String> {
+    let terms: Vec<_> = search
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Creates the FTS5 index over task text and the triggers keeping it in step
+/// with `tasks`, then rebuilds it in case rows changed while it was missing.
+/// Done at startup rather than in a migration so a SQLite built without FTS5
+/// can still run the server.
+async fn setup_fts(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    in_transaction(pool, async |tx| {
+        sqlx::raw_sql(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS tasks_fts
+                USING fts5(text, content='tasks', content_rowid='id');
+            CREATE TRIGGER IF NOT EXISTS tasks_fts_insert AFTER INSERT ON tasks BEGIN
+                INSERT INTO tasks_fts (rowid, text) VALUES (new.id, new.text);
+            END;
+            CREATE TRIGGER IF NOT EXISTS tasks_fts_delete AFTER DELETE ON tasks BEGIN
+                INSERT INTO tasks_fts (tasks_fts, rowid, text) VALUES ('delete', old.id, old.text);
+            END;
+            CREATE TRIGGER IF NOT EXISTS tasks_fts_update AFTER UPDATE OF text ON tasks BEGIN
+                INSERT INTO tasks_fts (tasks_fts, rowid, text) VALUES ('delete', old.id, old.text);
+                INSERT INTO tasks_fts (rowid, text) VALUES (new.id, new.text);
+            END;
+            INSERT INTO tasks_fts (tasks_fts) VALUES ('rebuild');",
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    })
+    .await
+}
+
+/// Inserts a new task, filed under `default_priority` when the payload
+/// doesn't give one, unless the list already holds `max_tasks`
+async fn insert_task(
+    pool: &SqlitePool,
+    payload: CreateTodo,
+    default_priority: Priority,
+    max_tasks: Option<u64>,
+) -> Result<Task, UpdateError> {
+    let task = in_transaction(pool, async |tx| {
+        let task = create_task(tx, payload, default_priority).await?;
+        check_task_limit(tx, max_tasks).await?;
+        Ok::<_, UpdateError>(task)
+    })
+    .await?;
+    counter!("todo_tasks_created_total").increment(1);
+    Ok(task)
+}
+
+/// Inserts one task with its dependency and tags, for callers that create
+/// tasks inside a larger transaction
+async fn create_task(
+    tx: &mut SqliteConnection,
+    payload: CreateTodo,
+    default_priority: Priority,
+) -> Result<Task, UpdateError> {
+    let priority = payload.priority.unwrap_or(default_priority);
+    let id = sqlx::query_scalar!(
+        "INSERT INTO tasks (text, done, priority, due, source, description)
+        values ($1, false, $2, $3, $4, $5) RETURNING id",
+        payload.text,
+        priority,
+        payload.due,
+        payload.source,
+        payload.description
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if let Some(depends_on) = payload.depends_on {
+        set_dependency(tx, id, Some(depends_on)).await?;
+    }
+    for tag in &payload.tags {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO task_tags (task_id, tag) VALUES ($1, $2)",
+            id,
+            tag
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = $1");
+    Ok(sqlx::query_as::<_, Task>(&sql)
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?)
+}
+
+async fn set_dependency(
+    tx: &mut SqliteConnection,
+    id: i64,
+    depends_on: Option<i64>,
+) -> Result<(), UpdateError> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND deleted_at IS NULL)
+        AS "exists!: bool""#,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        return Err(UpdateError::NotFound);
+    }
+    let Some(depends_on) = depends_on else {
+        sqlx::query!("DELETE FROM task_deps WHERE task_id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+        return Ok(());
+    };
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND deleted_at IS NULL)
+        AS "exists!: bool""#,
+        depends_on
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        return Err(UpdateError::MissingDependency(depends_on));
+    }
+    // follows the chain of dependencies starting at the new one, a cycle if
+    // it ever comes back to this task. UNION stops at rows already seen.
+    let cycle = sqlx::query_scalar!(
+        r#"WITH RECURSIVE chain (id) AS (
+            SELECT $1
+            UNION SELECT task_deps.depends_on FROM task_deps JOIN chain ON task_deps.task_id = chain.id
+        )
+        SELECT EXISTS (SELECT 1 FROM chain WHERE id = $2) AS "cycle!: bool""#,
+        depends_on,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if cycle {
+        return Err(UpdateError::DependencyCycle);
+    }
+    sqlx::query!(
+        "INSERT INTO task_deps (task_id, depends_on) VALUES ($1, $2)
+        ON CONFLICT (task_id) DO UPDATE SET depends_on = excluded.depends_on",
+        id,
+        depends_on
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Applies a partial update, returning whether a task with that id existed
+/// outside the trash.
+/// Marking a task done is refused while its dependency is unfinished, and
+/// any update is refused if `version` is set and no longer current.
+async fn apply_update(
+    pool: &SqlitePool,
+    id: i64,
+    payload: UpdateTodo,
+) -> Result<bool, UpdateError> {
+    // an Err rolls back the dependency change too
+    in_transaction(pool, async |tx| write_update(tx, id, &payload).await).await
+}
+
+/// `apply_update` for callers that update inside a larger transaction
+async fn write_update(
+    tx: &mut SqliteConnection,
+    id: i64,
+    payload: &UpdateTodo,
+) -> Result<bool, UpdateError> {
+    if let Some(depends_on) = payload.depends_on {
+        match set_dependency(tx, id, depends_on).await {
+            Err(UpdateError::NotFound) => return Ok(false),
+            result => result?,
+        }
+    }
+    if payload.done == Some(true) {
+        let blocked = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM task_deps JOIN tasks ON tasks.id = task_deps.depends_on
+            WHERE task_deps.task_id = $1 AND NOT tasks.done AND tasks.deleted_at IS NULL)
+            AS "blocked!: bool""#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if blocked {
+            return Err(UpdateError::Blocked);
+        }
+    }
+
+    // COALESCE returns first non null expression
+    // so either value from payload, or the value that's already set
+    // completed_at is stamped the first time a task becomes done and cleared
+    // when it's reopened, and an empty label or clear_due clears those.
+    // Every change bumps version, so a client holding an older one is refused
+    let now = Utc::now();
+    let result = sqlx::query!(
+        "UPDATE tasks SET text = COALESCE($1, text), done = COALESCE($2, done), priority = COALESCE($3, priority),
+        completed_at = CASE WHEN $2 IS NULL THEN completed_at WHEN $2 THEN COALESCE(completed_at, $4) ELSE NULL END,
+        due = CASE WHEN $11 THEN NULL ELSE COALESCE($5, due) END, estimate_minutes = COALESCE($6, estimate_minutes),
+        spent_minutes = COALESCE($7, spent_minutes),
+        label = CASE WHEN $8 IS NULL THEN label ELSE NULLIF($8, '') END,
+        version = version + 1
+        WHERE id = $9 AND deleted_at IS NULL AND ($10 IS NULL OR version = $10)",
+        payload.text,
+        payload.done,
+        payload.priority,
+        now,
+        payload.due,
+        payload.estimate_minutes,
+        payload.spent_minutes,
+        payload.label,
+        id,
+        payload.version,
+        payload.clear_due
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 && payload.version.is_some() {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND deleted_at IS NULL)
+            AS "exists!: bool""#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if exists {
+            return Err(UpdateError::Conflict);
+        }
+    }
+    Ok(result.rows_affected() > 0)
+}
+
+/// Toggles `done`, stamping or clearing `completed_at` to match. Finishing a
+/// task is refused while its dependency is unfinished, same as `apply_update`.
+async fn flip_done(pool: &SqlitePool, id: i64) -> Result<Task, UpdateError> {
+    let now = Utc::now();
+    // in SET, done still refers to the value before the update
+    let sql = format!(
+        "UPDATE tasks SET done = NOT done,
+        completed_at = CASE WHEN done THEN NULL ELSE COALESCE(completed_at, $1) END,
+        version = version + 1
+        WHERE id = $2 AND deleted_at IS NULL AND (done OR NOT EXISTS (
+            SELECT 1 FROM task_deps JOIN tasks AS dep ON dep.id = task_deps.depends_on
+            WHERE task_deps.task_id = tasks.id AND NOT dep.done AND dep.deleted_at IS NULL))
+        RETURNING {TASK_COLUMNS}"
+    );
+    let task = sqlx::query_as::<_, Task>(&sql)
+        .bind(now)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    if let Some(task) = task {
+        return Ok(task);
+    }
+
+    // nothing changed, either there's no such task or it's blocked
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND deleted_at IS NULL) AS "exists!: bool""#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+    if exists {
+        Err(UpdateError::Blocked)
+    } else {
+        Err(UpdateError::NotFound)
+    }
+}
+
+/// Inserts checklist items in one transaction, stamping `completed_at` on the
+/// ones already ticked off
+async fn import_tasks(
+    pool: &SqlitePool,
+    items: &[ChecklistItem],
+    default_priority: Priority,
+    max_tasks: Option<u64>,
+) -> Result<u64, UpdateError> {
+    let now = Utc::now();
+    in_transaction(pool, async |tx| {
+        for item in items {
+            let priority = item.priority.unwrap_or(default_priority);
+            let completed_at = item.done.then_some(now);
+            sqlx::query!(
+                "INSERT INTO tasks (text, done, priority, completed_at, source)
+                values ($1, $2, $3, $4, 'markdown')",
+                item.text,
+                item.done,
+                priority,
+                completed_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        check_task_limit(tx, max_tasks).await
+    })
+    .await?;
+    counter!("todo_tasks_created_total").increment(items.len() as u64);
+    Ok(items.len() as u64)
+}
+
+/// Every template with its tasks in order, sorted by name
+async fn select_templates(pool: &SqlitePool) -> Result<Vec<Template>, sqlx::Error> {
+    let mut templates: Vec<Template> =
+        sqlx::query!(r#"SELECT id AS "id!: i64", name FROM templates ORDER BY name"#)
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|row| Template {
+                id: row.id,
+                name: row.name,
+                tasks: Vec::new(),
+            })
+            .collect();
+    let rows = sqlx::query!(
+        r#"SELECT template_id, text, priority AS "priority: Priority" FROM template_tasks
+        ORDER BY template_id, position"#
+    )
+    .fetch_all(pool)
+    .await?;
+    for row in rows {
+        if let Some(template) = templates.iter_mut().find(|t| t.id == row.template_id) {
+            template.tasks.push(TemplateTask {
+                text: row.text,
+                priority: row.priority,
+            });
+        }
+    }
+    Ok(templates)
+}
+
+async fn insert_template(
+    pool: &SqlitePool,
+    name: &str,
+    tasks: Vec<TemplateTask>,
+) -> Result<Template, sqlx::Error> {
+    let id = in_transaction(pool, async |tx| {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO templates (name) VALUES ($1) RETURNING id",
+            name
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        for (position, task) in tasks.iter().enumerate() {
+            let position = position as i64;
+            sqlx::query!(
+                "INSERT INTO template_tasks (template_id, position, text, priority)
+                VALUES ($1, $2, $3, $4)",
+                id,
+                position,
+                task.text,
+                task.priority
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok::<_, sqlx::Error>(id)
+    })
+    .await?;
+    Ok(Template {
+        id,
+        name: name.to_string(),
+        tasks,
+    })
+}
+
+/// Creates the template's tasks in one transaction, returning them in order.
+/// They go through the same quick-add parsing as `POST /todos`, so
+/// `!high` and `#tag` in a template's text work there too.
+async fn insert_template_tasks(
+    pool: &SqlitePool,
+    template: &Template,
+    default_priority: Priority,
+    max_tasks: Option<u64>,
+) -> Result<Vec<Task>, UpdateError> {
+    let created = in_transaction(pool, async |tx| {
+        let mut created = Vec::with_capacity(template.tasks.len());
+        for task in &template.tasks {
+            let mut payload = CreateTodo {
+                text: task.text.clone(),
+                priority: task.priority,
+                due: None,
+                depends_on: None,
+                source: Some("template".to_string()),
+                description: None,
+                tags: Vec::new(),
+            };
+            payload.parse_quick_add();
+            created.push(create_task(tx, payload, default_priority).await?);
+        }
+        check_task_limit(tx, max_tasks).await?;
+        Ok::<_, UpdateError>(created)
+    })
+    .await?;
+    counter!("todo_tasks_created_total").increment(created.len() as u64);
+    Ok(created)
+}
+
+/// Marks every open, unblocked task matching the filter done in a single
+/// statement, returning how many were changed
+async fn complete_tasks(
+    pool: &SqlitePool,
+    params: &TaskQuery,
+    search_index: SearchIndex,
+) -> Result<u64, sqlx::Error> {
+    let mut query = QueryBuilder::new("UPDATE tasks SET done = true, completed_at = ");
+    query.push_bind(Utc::now());
+    query.push(
+        ", version = version + 1 WHERE NOT done AND NOT EXISTS (SELECT 1 FROM task_deps JOIN tasks AS dep
+        ON dep.id = task_deps.depends_on WHERE task_deps.task_id = tasks.id AND NOT dep.done
+        AND dep.deleted_at IS NULL)
+        AND id IN (SELECT id FROM tasks",
+    );
+    apply_task_query(&mut query, params, search_index);
+    query.push(")");
+
+    let result = query.build().execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+/// Runs `f` in a transaction that's committed when it returns `Ok` and
+/// rolled back on `Err`, so a multi-statement write either lands whole or
+/// not at all
+async fn in_transaction<T, E, F>(pool: &SqlitePool, f: F) -> Result<T, E>
+where
+    F: AsyncFnOnce(&mut SqliteConnection) -> Result<T, E>,
+    E: From<sqlx::Error>,
+{
+    let mut tx = pool.begin().await?;
+    // on Err, dropping tx rolls it back, and a failed rollback mustn't
+    // hide the error that caused it
+    let value = f(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(value)
+}
+
+/// Pushes each overdue, undone task's due date forward by whole days until
+/// it's after `now`, keeping its time of day, and returns how many moved
+async fn roll_over_overdue(pool: &SqlitePool, now: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    in_transaction(pool, async |tx| {
+        let overdue = sqlx::query!(
+            r#"SELECT id AS "id!: i64", due AS "due!: DateTime<Utc>" FROM tasks
+            WHERE NOT done AND due IS NOT NULL AND due < $1 AND deleted_at IS NULL"#,
+            now
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for task in &overdue {
+            let due = next_due(task.due, now);
+            sqlx::query!(
+                "UPDATE tasks SET due = $1, version = version + 1 WHERE id = $2",
+                due,
+                task.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(overdue.len() as u64)
+    })
+    .await
+}
+
+async fn normalize_priorities(
+    pool: &SqlitePool,
+    strategy: &Normalization,
+    now: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    in_transaction(pool, async |tx| {
+        // most recently changed first, which is the order CapHigh keeps them in
+        let open = sqlx::query!(
+            r#"SELECT id AS "id!: i64", priority AS "priority: Priority", updated_at FROM tasks
+            WHERE NOT done AND deleted_at IS NULL
+            ORDER BY updated_at DESC, id DESC"#
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let changes: Vec<(i64, Priority)> = match *strategy {
+            Normalization::DemoteStale { days } => {
+                // a cutoff from before the earliest date there is can't
+                // catch anything
+                let Some(cutoff) = now.checked_sub_signed(TimeDelta::days(days.into())) else {
+                    return Ok(0);
+                };
+                let cutoff = sqlite_timestamp(cutoff);
+                open.iter()
+                    .filter(|task| {
+                        task.updated_at
+                            .as_deref()
+                            .is_some_and(|at| at < cutoff.as_str())
+                    })
+                    // Low has nowhere lower to go
+                    .filter(|task| task.priority != Priority::Low)
+                    .map(|task| (task.id, task.priority.lowered()))
+                    .collect()
+            }
+            Normalization::CapHigh { keep } => open
+                .iter()
+                .filter(|task| task.priority == Priority::High)
+                .skip(keep as usize)
+                .map(|task| (task.id, Priority::Medium))
+                .collect(),
+        };
+
+        for &(id, priority) in &changes {
+            sqlx::query!(
+                "UPDATE tasks SET priority = $1, version = version + 1 WHERE id = $2",
+                priority,
+                id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(changes.len() as u64)
+    })
+    .await
+}
+
+/// The first occurrence of `due`'s time of day that's after `now`
+fn next_due(due: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+    // num_days rounds down, so one more day always lands after now
+    due + TimeDelta::days((now - due).num_days() + 1)
+}
+
+/// Moves a task to the trash, returning whether a task with that id was
+/// there to delete
+async fn remove_task(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        "UPDATE tasks SET deleted_at = $1 WHERE id = $2 AND deleted_at IS NULL",
+        now,
+        id
+    )
+    .execute(pool)
+    .await?;
+    counter!("todo_tasks_deleted_total").increment(result.rows_affected());
+    Ok(result.rows_affected() > 0)
+}
+
+/// Updates every task matching the filter, only those in `ids` when given, in
+/// one transaction, returning how many there were
+async fn update_tasks(
+    pool: &SqlitePool,
+    params: &TaskQuery,
+    search_index: SearchIndex,
+    ids: Option<&[i64]>,
+    changes: &UpdateTodo,
+) -> Result<u64, UpdateError> {
+    in_transaction(pool, async |tx| {
+        let mut query = QueryBuilder::new("SELECT id FROM tasks");
+        apply_task_query(&mut query, params, search_index);
+        if let Some(ids) = ids {
+            query.push(" AND id IN (");
+            let mut separated = query.separated(", ");
+            for &id in ids {
+                separated.push_bind(id);
+            }
+            query.push(")");
+        }
+        query.push(" ORDER BY id");
+        let matching: Vec<i64> = query.build_query_scalar().fetch_all(&mut *tx).await?;
+        for &id in &matching {
+            write_update(tx, id, changes).await?;
+        }
+        Ok(matching.len() as u64)
+    })
+    .await
+}
+
+/// Moves every task matching the filter to the trash, returning how many
+async fn remove_tasks(
+    pool: &SqlitePool,
+    params: &TaskQuery,
+    search_index: SearchIndex,
+) -> Result<u64, sqlx::Error> {
+    let mut query = QueryBuilder::new("UPDATE tasks SET deleted_at = ");
+    query.push_bind(Utc::now());
+    query.push(" WHERE id IN (SELECT id FROM tasks");
+    apply_task_query(&mut query, params, search_index);
+    query.push(")");
+
+    let result = query.build().execute(pool).await?;
+    counter!("todo_tasks_deleted_total").increment(result.rows_affected());
+    Ok(result.rows_affected())
+}
+
+/// Takes a task back out of the trash, returning whether it was in there.
+/// Fails instead if that would take the list past `max_tasks`.
+async fn restore_task(
+    pool: &SqlitePool,
+    id: i64,
+    max_tasks: Option<u64>,
+) -> Result<bool, UpdateError> {
+    in_transaction(pool, async |tx| {
+        let result = sqlx::query!(
+            "UPDATE tasks SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL",
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        check_task_limit(tx, max_tasks).await?;
+        Ok(true)
+    })
+    .await
+}
+
+/// Deletes a task for good, trashed or not, returning whether it existed
+async fn purge_task(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM tasks WHERE id = $1", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Writes a consistent snapshot of the database while the server keeps
+/// running. Shares the `DEV_MODE=1` guard with reset.
+#[instrument(skip(state))]
+async fn backup(State(state): State<Arc<AppState>>) -> Result<Json<Backup>, AppError> {
+    counter!("todo_requests_total", "route" => "backup").increment(1);
+    if !state.dev_mode {
+        info!("Refusing backup, DEV_MODE is not enabled");
+        return Err(AppError::Forbidden("backups over HTTP need DEV_MODE=1"));
+    }
+    let backup = write_backup(&state.pool, &state.backup_dir).await?;
+    info!("Backup written to {:?}", backup.path);
+    Ok(Json(backup))
+}
+
+/// Opens the pool, retrying up to `attempts` times in all with the wait
+/// doubling after each failure. Returns the last error once out of attempts.
+async fn connect_with_backoff(
+    options: SqliteConnectOptions,
+    attempts: u32,
+) -> Result<SqlitePool, sqlx::Error> {
+    let mut attempt = 1;
+    loop {
+        match SqlitePoolOptions::new().connect_with(options.clone()).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) if attempt < attempts => {
+                let wait = connect_backoff(attempt);
+                warn!(
+                    "Database connection attempt {attempt}/{attempts} failed, retrying in {wait:?}: {e}"
+                );
+                tokio::time::sleep(wait).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// How long to wait after failed connection attempt number `attempt`,
+/// counting from 1
+fn connect_backoff(attempt: u32) -> Duration {
+    CONNECT_BACKOFF_START
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(CONNECT_BACKOFF_MAX)
+}
+
+/// Formats `at` the way the `updated_at` triggers do, so the two compare
+/// correctly as text. Anything finer than a millisecond is dropped.
+fn sqlite_timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+async fn select_changes(pool: &SqlitePool, since: DateTime<Utc>) -> Result<Changes, sqlx::Error> {
+    // taken before reading so nothing written during the reads is skipped
+    // next time
+    let as_of = Utc::now();
+    let since = sqlite_timestamp(since);
+    let sql = format!(
+        "SELECT {TASK_COLUMNS} FROM tasks \
+        WHERE deleted_at IS NULL AND updated_at >= $1 ORDER BY id"
+    );
+    let upserts = sqlx::query_as::<_, Task>(&sql)
+        .bind(&since)
+        .fetch_all(pool)
+        .await?;
+    let tombstones = sqlx::query_scalar(
+        "SELECT id FROM tasks WHERE deleted_at IS NOT NULL AND updated_at >= $1 ORDER BY id",
+    )
+    .bind(&since)
+    .fetch_all(pool)
+    .await?;
+    Ok(Changes {
+        upserts,
+        tombstones,
+        as_of,
+    })
+}
+
+/// Snapshots the database into a new timestamped file in `dir` with
+/// `VACUUM INTO`, which refuses to overwrite an existing file
+async fn write_backup(pool: &SqlitePool, dir: &std::path::Path) -> Result<Backup, BackupError> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(BackupError::CreateDir)?;
+    let path = dir.join(format!(
+        "todos-{}.db",
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    sqlx::query("VACUUM INTO $1")
+        .bind(path.to_string_lossy().into_owned())
+        .execute(pool)
+        .await?;
+    let size = tokio::fs::metadata(&path)
+        .await
+        .map_err(BackupError::Metadata)?
+        .len();
+    Ok(Backup { path, size })
+}
+
+/// Deletes every task and dependency, and resets the id counter so new tasks
+/// start from 1 again
+async fn clear_tasks(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let deleted = in_transaction(pool, async |tx| {
+        sqlx::query!("DELETE FROM task_deps")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM task_tags")
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query!("DELETE FROM tasks").execute(&mut *tx).await?;
+        sqlx::query!("DELETE FROM sqlite_sequence WHERE name = 'tasks'")
+            .execute(&mut *tx)
+            .await?;
+        Ok::<_, sqlx::Error>(result.rows_affected())
+    })
+    .await?;
+    counter!("todo_tasks_deleted_total").increment(deleted);
+    Ok(deleted)
+}
+
+#[instrument(skip(state))]
+async fn render_metrics(State(state): State<Arc<AppState>>) -> Result<String, AppError> {
+    // task counts are read at scrape time rather than tracked per handler,
+    // so they stay correct even if the db is edited by hand
+    let counts = sqlx::query!(
+        r#"SELECT COUNT(*) AS "total!: i64", COALESCE(SUM(done), 0) AS "done!: i64" FROM tasks
+        WHERE deleted_at IS NULL"#
+    )
+    .fetch_one(&state.pool)
+    .await?;
+    gauge!("todo_tasks", "done" => "true").set(counts.done as f64);
+    gauge!("todo_tasks", "done" => "false").set((counts.total - counts.done) as f64);
+
+    Ok(state.metrics.render())
+}
+
+/// Answers anything but reads with 403 when the server is read-only. GraphQL
+/// and the chat webhook are let through since their reads are POSTs too,
+/// and they check the flag themselves before writing.
+async fn refuse_writes(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let checks_itself = matches!(request.uri().path(), "/graphql" | "/todos/webhook");
+    if !state.read_only || read || checks_itself {
+        return next.run(request).await;
+    }
+    AppError::Forbidden(READ_ONLY_MESSAGE).into_response()
+}
+
+async fn count_errors(response: Response) -> Response {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        counter!("todo_errors_total", "status" => status.as_u16().to_string()).increment(1);
+    }
+    response
+}
+
+async fn handle_timeout(err: BoxError) -> StatusCode {
+    if err.is::<tower::timeout::error::Elapsed>() {
+        StatusCode::REQUEST_TIMEOUT
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+fn body_etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Whether any entity tag in `If-None-Match` is `etag`, comparing weakly as
+/// the spec asks for GET
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The task version from `If-Match`, quoted like an entity tag (`"3"`) or bare
+fn if_match_version(headers: &HeaderMap) -> Option<i64> {
+    headers
+        .get(header::IF_MATCH)?
+        .to_str()
+        .ok()?
+        .trim()
+        .trim_matches('"')
+        .parse()
+        .ok()
+}
+
+/// Where a new task came from when the body doesn't say: the source header,
+/// or failing that the client's user agent
+fn request_source(headers: &HeaderMap) -> Option<String> {
+    [SOURCE_HEADER, header::USER_AGENT.as_str()]
+        .into_iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .map(str::trim)
+        .find(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn wants_json_api(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| media_type.trim().starts_with(JSON_API_MEDIA_TYPE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every connection to :memory: gets its own database, so the pool keeps
+    /// to one
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    /// A new task with nothing but its text set
+    fn create(text: &str) -> CreateTodo {
+        CreateTodo {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn filter_sql(params: &TaskQuery) -> (String, usize) {
+        let mut query = QueryBuilder::new("SELECT id FROM tasks");
+        let binds = apply_task_query(&mut query, params, SearchIndex::Like);
+        (query.sql().to_string(), binds)
+    }
+
+    #[test]
+    fn empty_query_only_hides_deleted() {
+        assert_eq!(
+            filter_sql(&TaskQuery::default()),
+            (
+                "SELECT id FROM tasks WHERE deleted_at IS NULL".to_string(),
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn conditions_are_joined_with_and() {
+        let params = TaskQuery {
+            done: Some(false),
+            priority: Some(Priority::High),
+            after_id: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter_sql(&params),
+            (
+                "SELECT id FROM tasks WHERE deleted_at IS NULL AND done = ? AND priority = ? AND id > ?"
+                    .to_string(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn search_is_an_escaped_like() {
+        let params = TaskQuery {
+            search: Some("milk".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter_sql(&params),
+            (
+                "SELECT id FROM tasks WHERE deleted_at IS NULL AND text LIKE ? ESCAPE '\\'"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_keep_id_order_after_edits() {
+        let pool = test_pool().await;
+
+        for text in ["first", "second", "third"] {
+            insert_task(&pool, create(text), Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let payload = UpdateTodo {
+            text: Some("first, edited".to_string()),
+            done: Some(true),
+            priority: Some(Priority::High),
+            ..Default::default()
+        };
+        apply_update(&pool, 1, payload).await.unwrap();
+
+        let ids = |tasks: Vec<Task>| tasks.iter().map(|t| t.id).collect::<Vec<_>>();
+        let params = TaskQuery::default();
+        let first = ids(select_tasks(&pool, &params, SearchIndex::Like, false)
+            .await
+            .unwrap());
+        let second = ids(select_tasks(&pool, &params, SearchIndex::Like, false)
+            .await
+            .unwrap());
+        assert_eq!(first, [1, 2, 3]);
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn markdown_round_trip_keeps_tasks() {
+        let pool = test_pool().await;
+        let state = Arc::new(AppState {
+            pool: pool.clone(),
+            metrics: PrometheusBuilder::new().build_recorder().handle(),
+            dev_mode: false,
+            default_priority: Priority::Medium,
+            backup_dir: PathBuf::new(),
+            search_index: SearchIndex::Like,
+            max_tasks: None,
+            read_only: false,
+        });
+        let export = async || {
+            let response = export_markdown(State(state.clone())).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        let markdown = "# Release\n\
+            - [x] (H) fix [bug] in the ] parser\n\
+            - [ ] (M) [ ] looks like a box\n\
+            * [ ] (L) (H) is part of the text\n\
+            - [X] ship it\n";
+        let Json(count) = import_markdown(State(state.clone()), markdown.to_string())
+            .await
+            .unwrap();
+        assert_eq!(count.imported, 4);
+
+        // the heading is dropped and the missing priority filled in
+        let exported = export().await;
+        assert_eq!(
+            exported,
+            "- [x] (H) fix [bug] in the ] parser\n\
+             - [ ] (M) [ ] looks like a box\n\
+             - [ ] (L) (H) is part of the text\n\
+             - [x] (M) ship it\n"
+        );
+
+        clear_tasks(&pool).await.unwrap();
+        let Json(count) = import_markdown(State(state.clone()), exported.clone())
+            .await
+            .unwrap();
+        assert_eq!(count.imported, 4);
+        assert_eq!(export().await, exported);
+        let tasks = select_tasks(&pool, &TaskQuery::default(), SearchIndex::Like, false)
+            .await
+            .unwrap();
+        assert!(tasks.iter().all(|t| t.done == t.completed_at.is_some()));
+    }
+
+    #[tokio::test]
+    async fn stale_version_is_refused() {
+        let pool = test_pool().await;
+        let task = insert_task(&pool, create("shared"), Priority::Low, None)
+            .await
+            .unwrap();
+        let rename = |text: &str| UpdateTodo {
+            text: Some(text.to_string()),
+            version: Some(task.version),
+            ..Default::default()
+        };
+
+        assert!(apply_update(&pool, task.id, rename("first")).await.unwrap());
+        assert!(matches!(
+            apply_update(&pool, task.id, rename("second")).await,
+            Err(UpdateError::Conflict)
+        ));
+        let tasks = select_tasks(&pool, &TaskQuery::default(), SearchIndex::Like, false)
+            .await
+            .unwrap();
+        assert_eq!(tasks[0].text, "first");
+        assert_eq!(tasks[0].version, task.version + 1);
+    }
+
+    #[test]
+    fn grouping_keeps_order_within_each_priority() {
+        let tasks = [
+            (1, Priority::Low),
+            (2, Priority::High),
+            (3, Priority::Low),
+            (4, Priority::Medium),
+        ]
+        .map(|(id, priority)| Task {
+            id,
+            priority,
+            ..Default::default()
+        });
+        let grouped = GroupedTasks::from(Vec::from(tasks));
+        let ids = |tasks: &[Task]| tasks.iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(&grouped.high), [2]);
+        assert_eq!(ids(&grouped.medium), [4]);
+        assert_eq!(ids(&grouped.low), [1, 3]);
+    }
+
+    #[test]
+    fn email_subject_becomes_task() {
+        let email = EmailTodo {
+            subject: "Re: [urgent]  renew  passport".to_string(),
+            body: "  expires next month\n".to_string(),
+        };
+        let task = email.into_task().unwrap();
+        assert_eq!(task.text, "Re: renew passport");
+        assert_eq!(task.priority, Some(Priority::High));
+        assert_eq!(task.description.as_deref(), Some("expires next month"));
+
+        let email = EmailTodo {
+            subject: "water plants".to_string(),
+            body: String::new(),
+        };
+        let task = email.into_task().unwrap();
+        assert_eq!(task.priority, None);
+        assert_eq!(task.description, None);
+
+        let email = EmailTodo {
+            subject: " [URGENT] ".to_string(),
+            body: "no subject".to_string(),
+        };
+        assert!(email.into_task().is_none());
+    }
+
+    #[test]
+    fn source_header_wins_over_user_agent() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_source(&headers), None);
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("curl/8.5.0"));
+        assert_eq!(request_source(&headers).as_deref(), Some("curl/8.5.0"));
+        headers.insert(SOURCE_HEADER, HeaderValue::from_static("email-gateway"));
+        assert_eq!(request_source(&headers).as_deref(), Some("email-gateway"));
+    }
+
+    #[test]
+    fn checklist_lines_parse() {
+        let item = |text: &str, done, priority| ChecklistItem {
+            text: text.to_string(),
+            done,
+            priority,
+        };
+        assert_eq!(
+            ChecklistItem::parse("- [ ] buy milk"),
+            Some(item("buy milk", false, None))
+        );
+        assert_eq!(
+            ChecklistItem::parse("  * [X] (H) file taxes "),
+            Some(item("file taxes", true, Some(Priority::High)))
+        );
+        assert_eq!(
+            ChecklistItem::parse("- [x] (later) call mum"),
+            Some(item("(later) call mum", true, None))
+        );
+        assert_eq!(ChecklistItem::parse("- [ ]"), None);
+        assert_eq!(ChecklistItem::parse("- plain bullet"), None);
+        assert_eq!(ChecklistItem::parse("## Heading"), None);
+    }
+
+    #[test]
+    fn completion_pct_of_empty_list_is_zero() {
+        assert_eq!(completion_pct(0, 0), 0.0);
+        assert_eq!(completion_pct(1, 4), 25.0);
+        assert_eq!(completion_pct(3, 3), 100.0);
+
+        let stats = TimeStats {
+            estimate_minutes: 0,
+            spent_minutes: 0,
+            total: 0,
+            done: 0,
+            completion_pct: completion_pct(0, 0),
+            low: 0,
+            medium: 0,
+            high: 0,
+        };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["completion_pct"], 0.0);
+    }
+
+    #[test]
+    fn json_is_compact_unless_pretty() {
+        let value = serde_json::json!({ "id": 1 });
+        assert_eq!(json_bytes(&value, false).unwrap(), br#"{"id":1}"#);
+        assert_eq!(json_bytes(&value, true).unwrap(), b"{\n  \"id\": 1\n}");
+    }
+
+    #[test]
+    fn if_match_version_accepts_quoted_or_bare() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(if_match_version(&headers), None);
+        headers.insert(header::IF_MATCH, HeaderValue::from_static("\"3\""));
+        assert_eq!(if_match_version(&headers), Some(3));
+        headers.insert(header::IF_MATCH, HeaderValue::from_static("4"));
+        assert_eq!(if_match_version(&headers), Some(4));
+        headers.insert(header::IF_MATCH, HeaderValue::from_static("*"));
+        assert_eq!(if_match_version(&headers), None);
+    }
+
+    #[test]
+    fn etag_matches_any_listed_tag() {
+        let etag = body_etag(b"[]");
+        let mut headers = HeaderMap::new();
+        assert!(!etag_matches(&headers, &etag));
+
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("\"stale\", W/{etag}")).unwrap(),
+        );
+        assert!(etag_matches(&headers, &etag));
+        assert!(!etag_matches(&headers, &body_etag(b"[{}]")));
+    }
+
+    #[test]
+    fn next_due_keeps_time_of_day() {
+        let now = "2026-10-17T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let morning = "2026-10-14T09:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let evening = "2026-10-14T18:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            next_due(morning, now),
+            "2026-10-18T09:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            next_due(evening, now),
+            "2026-10-17T18:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn fts_search_quotes_each_word() {
+        assert_eq!(
+            fts_query(" buy \"oat\" milk").as_deref(),
+            Some(r#""buy"* """oat"""* "milk"*"#)
+        );
+        assert_eq!(fts_query("   "), None);
+
+        let params = TaskQuery {
+            search: Some("milk".to_string()),
+            ..Default::default()
+        };
+        let mut query = QueryBuilder::new("SELECT id FROM tasks");
+        let binds = apply_task_query(&mut query, &params, SearchIndex::Fts);
+        assert_eq!(
+            query.sql(),
+            "SELECT id FROM tasks WHERE deleted_at IS NULL \
+            AND id IN (SELECT rowid FROM tasks_fts WHERE tasks_fts MATCH ?)"
+        );
+        assert_eq!(binds, 1);
+
+        // exact matches still need the whole text compared
+        let params = TaskQuery {
+            match_mode: Some(MatchMode::Exact),
+            ..params
+        };
+        let mut query = QueryBuilder::new("SELECT id FROM tasks");
+        apply_task_query(&mut query, &params, SearchIndex::Fts);
+        assert!(query.sql().ends_with("AND text LIKE ? ESCAPE '\\'"));
+    }
+
+    #[tokio::test]
+    async fn fts_index_follows_edits() {
+        let pool = test_pool().await;
+        setup_fts(&pool).await.unwrap();
+        for text in ["buy milk", "call the milkman", "pay rent"] {
+            insert_task(&pool, create(text), Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let search = |term: &str| TaskQuery {
+            search: Some(term.to_string()),
+            ..Default::default()
+        };
+        let ids = |tasks: Vec<Task>| tasks.iter().map(|t| t.id).collect::<Vec<_>>();
+
+        let query = search("milk");
+        let found = select_tasks(&pool, &query, SearchIndex::Fts, false);
+        assert_eq!(ids(found.await.unwrap()), [1, 2]);
+
+        let payload = UpdateTodo {
+            text: Some("pay rent and buy milk".to_string()),
+            ..Default::default()
+        };
+        apply_update(&pool, 3, payload).await.unwrap();
+        let query = search("rent milk");
+        let found = select_tasks(&pool, &query, SearchIndex::Fts, false);
+        assert_eq!(ids(found.await.unwrap()), [3]);
+    }
+
+    #[test]
+    fn paging_is_left_to_the_caller() {
+        let params = TaskQuery {
+            limit: Some(20),
+            ..Default::default()
+        };
+        let (sql, binds) = filter_sql(&params);
+        assert!(!sql.contains("LIMIT"));
+        assert_eq!(binds, 0);
+    }
+
+    #[test]
+    fn app_errors_map_to_status_codes() {
+        let status = |e: AppError| e.into_response().status();
+        assert_eq!(status(AppError::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(status(UpdateError::Blocked.into()), StatusCode::CONFLICT);
+        assert_eq!(
+            status(AppError::LimitReached("full".to_string())),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            status(sqlx::Error::RowNotFound.into()),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn task_id_hint_is_the_first_words() {
+        assert_eq!(
+            TaskId::new(4, "  call the  bank about the card"),
+            TaskId {
+                id: 4,
+                hint: "call the bank".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_priority_lists_the_valid_ones() {
+        let create: CreateTodo =
+            serde_json::from_str(r#"{"text": "ship it", "priority": "high"}"#).unwrap();
+        assert_eq!(create.priority, Some(Priority::High));
+        let update: UpdateTodo = serde_json::from_str("{}").unwrap();
+        assert_eq!(update.priority, None);
+        let error = serde_json::from_str::<UpdateTodo>(r#"{"priority": "urgent"}"#).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with(r#"unknown priority "urgent", expected one of Low, Medium, High"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_since_is_inclusive() {
+        let pool = test_pool().await;
+        for text in ["old", "edited", "trashed", "untouched"] {
+            insert_task(&pool, create(text), Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        remove_task(&pool, 3).await.unwrap();
+        let since = "2020-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let touched_at = |id: i64, at: DateTime<Utc>| {
+            sqlx::query("UPDATE tasks SET updated_at = $1 WHERE id = $2")
+                .bind(sqlite_timestamp(at))
+                .bind(id)
+                .execute(&pool)
+        };
+        touched_at(1, since - TimeDelta::milliseconds(1))
+            .await
+            .unwrap();
+        touched_at(2, since).await.unwrap();
+        touched_at(3, since + TimeDelta::hours(1)).await.unwrap();
+        touched_at(4, since - TimeDelta::days(1)).await.unwrap();
+
+        let changes = select_changes(&pool, since).await.unwrap();
+        let upserts: Vec<i64> = changes.upserts.iter().map(|t| t.id).collect();
+        assert_eq!(upserts, [2]);
+        assert_eq!(changes.tombstones, [3]);
+
+        // the next sync starting from `as_of` sees later edits only
+        let next = changes.as_of;
+        assert!(
+            select_changes(&pool, next)
+                .await
+                .unwrap()
+                .upserts
+                .is_empty()
+        );
+        let payload = UpdateTodo {
+            done: Some(true),
+            ..Default::default()
+        };
+        apply_update(&pool, 4, payload).await.unwrap();
+        let upserts = select_changes(&pool, next).await.unwrap().upserts;
+        assert_eq!(upserts.iter().map(|t| t.id).collect::<Vec<_>>(), [4]);
+    }
+
+    #[tokio::test]
+    async fn dependency_changes_touch_the_dependent() {
+        let pool = test_pool().await;
+        for text in ["dependency", "dependent"] {
+            insert_task(&pool, create(text), Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let since = Utc::now() - TimeDelta::hours(1);
+        let changed_since = async |id: i64, payload: UpdateTodo| {
+            sqlx::query("UPDATE tasks SET updated_at = $1")
+                .bind(sqlite_timestamp(since - TimeDelta::hours(1)))
+                .execute(&pool)
+                .await
+                .unwrap();
+            apply_update(&pool, id, payload).await.unwrap();
+            let changes = select_changes(&pool, since).await.unwrap();
+            changes.upserts.iter().map(|t| t.id).collect::<Vec<_>>()
+        };
+
+        // finishing the dependency unblocks the dependent, a new label doesn't
+        let depend = UpdateTodo {
+            depends_on: Some(Some(1)),
+            ..Default::default()
+        };
+        assert_eq!(changed_since(2, depend).await, [2]);
+        let done = UpdateTodo {
+            done: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(changed_since(1, done).await, [1, 2]);
+        let label = UpdateTodo {
+            label: Some("home".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(changed_since(1, label).await, [1]);
+    }
+
+    #[test]
+    fn connect_backoff_doubles_up_to_the_cap() {
+        let waits: Vec<u64> = (1..=8)
+            .map(|attempt| connect_backoff(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(waits, [250, 500, 1000, 2000, 4000, 8000, 10000, 10000]);
+        assert_eq!(connect_backoff(40), CONNECT_BACKOFF_MAX);
+    }
+
+    #[tokio::test]
+    async fn templates_apply_in_order() {
+        let pool = test_pool().await;
+        let tasks = vec![
+            TemplateTask {
+                text: "bump version".to_string(),
+                priority: None,
+            },
+            TemplateTask {
+                text: "tag release".to_string(),
+                priority: Some(Priority::High),
+            },
+        ];
+        let saved = insert_template(&pool, "release", tasks).await.unwrap();
+        let duplicate = insert_template(&pool, "release", Vec::new()).await;
+        assert!(matches!(duplicate, Err(sqlx::Error::Database(e)) if e.is_unique_violation()));
+
+        let templates = select_templates(&pool).await.unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].tasks, saved.tasks);
+
+        let created = insert_template_tasks(&pool, &templates[0], Priority::Medium, None)
+            .await
+            .unwrap();
+        let summary: Vec<(&str, Priority)> = created
+            .iter()
+            .map(|t| (t.text.as_str(), t.priority))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("bump version", Priority::Medium),
+                ("tag release", Priority::High)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn normalize_priority_strategies() {
+        let pool = test_pool().await;
+        let tasks = [
+            ("stale high", Priority::High),
+            ("stale medium", Priority::Medium),
+            ("stale low", Priority::Low),
+            ("stale done", Priority::High),
+            ("fresh high", Priority::High),
+            ("fresher high", Priority::High),
+        ];
+        for (text, priority) in tasks {
+            let payload = CreateTodo {
+                priority: Some(priority),
+                ..create(text)
+            };
+            insert_task(&pool, payload, Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let payload = UpdateTodo {
+            done: Some(true),
+            ..Default::default()
+        };
+        apply_update(&pool, 4, payload).await.unwrap();
+        let now = "2020-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        for (id, age) in [(1, 40), (2, 31), (3, 90), (4, 60), (5, 2), (6, 1)] {
+            sqlx::query("UPDATE tasks SET updated_at = $1 WHERE id = $2")
+                .bind(sqlite_timestamp(now - TimeDelta::days(age)))
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        async fn priorities(pool: &SqlitePool) -> Vec<Priority> {
+            sqlx::query_scalar("SELECT priority FROM tasks ORDER BY id")
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        }
+
+        let stale = Normalization::DemoteStale { days: 30 };
+        assert_eq!(normalize_priorities(&pool, &stale, now).await.unwrap(), 2);
+        use Priority::{High, Low, Medium};
+        assert_eq!(
+            priorities(&pool).await,
+            [Medium, Low, Low, High, High, High]
+        );
+
+        let forever = Normalization::DemoteStale { days: u32::MAX };
+        assert_eq!(normalize_priorities(&pool, &forever, now).await.unwrap(), 0);
+
+        let cap = Normalization::CapHigh { keep: 1 };
+        assert_eq!(normalize_priorities(&pool, &cap, now).await.unwrap(), 1);
+        assert_eq!(
+            priorities(&pool).await,
+            [Medium, Low, Low, High, Medium, High]
+        );
+
+        let strategy: Normalization =
+            serde_json::from_str(r#"{"strategy": "demote_stale", "days": 30}"#).unwrap();
+        assert!(matches!(strategy, Normalization::DemoteStale { days: 30 }));
+    }
+
+    #[tokio::test]
+    async fn transactions_roll_back_on_error() {
+        let pool = test_pool().await;
+        let insert = "INSERT INTO tasks (text, done, priority) VALUES ('kept', false, 'Low')";
+
+        let result: Result<(), AppError> = in_transaction(&pool, async |tx| {
+            sqlx::query(insert).execute(&mut *tx).await?;
+            Err(AppError::Conflict("changed meanwhile".to_string()))
+        })
+        .await;
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tasks")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let id = in_transaction::<_, sqlx::Error, _>(&pool, async |tx| {
+            sqlx::query(insert).execute(&mut *tx).await?;
+            sqlx::query_scalar("SELECT last_insert_rowid()")
+                .fetch_one(&mut *tx)
+                .await
+        })
+        .await
+        .unwrap();
+        let text: String = sqlx::query_scalar("SELECT text FROM tasks WHERE id = $1")
+            .bind::<i64>(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(text, "kept");
+    }
+
+    #[tokio::test]
+    async fn trashed_tasks_neither_block_nor_change() {
+        let pool = test_pool().await;
+        for (text, depends_on) in [("dependency", None), ("dependent", Some(1))] {
+            let payload = CreateTodo {
+                depends_on,
+                ..create(text)
+            };
+            insert_task(&pool, payload, Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let done = || UpdateTodo {
+            done: Some(true),
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_update(&pool, 2, done()).await,
+            Err(UpdateError::Blocked)
+        ));
+
+        remove_task(&pool, 1).await.unwrap();
+        assert!(!apply_update(&pool, 1, done()).await.unwrap());
+        let query = TaskQuery::default();
+        let tasks = select_tasks(&pool, &query, SearchIndex::Like, false)
+            .await
+            .unwrap();
+        assert!(!tasks[0].blocked);
+        assert!(apply_update(&pool, 2, done()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn task_limit_ignores_the_trash() {
+        let pool = test_pool().await;
+        let limit = Some(2);
+        for text in ["kept", "trashed"] {
+            insert_task(&pool, create(text), Priority::Low, limit)
+                .await
+                .unwrap();
+        }
+        let full = insert_task(&pool, create("one too many"), Priority::Low, limit).await;
+        assert!(matches!(full, Err(UpdateError::LimitReached(2))));
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tasks")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+
+        remove_task(&pool, 2).await.unwrap();
+        let task = insert_task(&pool, create("fits again"), Priority::Low, limit)
+            .await
+            .unwrap();
+        // a missing task is reported as missing even when the list is full
+        assert!(!restore_task(&pool, 99, limit).await.unwrap());
+        assert!(matches!(
+            restore_task(&pool, 2, limit).await,
+            Err(UpdateError::LimitReached(2))
+        ));
+        remove_task(&pool, task.id).await.unwrap();
+        assert!(restore_task(&pool, 2, limit).await.unwrap());
+    }
+
+    #[test]
+    fn quick_add_tokens_fill_in_the_payload() {
+        let mut payload = create("buy milk !high #shopping");
+        payload.parse_quick_add();
+        assert_eq!(payload.text, "buy milk");
+        assert_eq!(payload.priority, Some(Priority::High));
+        assert_eq!(payload.tags, ["shopping"]);
+
+        let mut payload = CreateTodo {
+            priority: Some(Priority::Low),
+            ..create("call mum !high")
+        };
+        payload.parse_quick_add();
+        assert_eq!(payload.priority, Some(Priority::Low));
+    }
+
+    #[tokio::test]
+    async fn bulk_updates_are_all_or_none() {
+        let pool = test_pool().await;
+        for (text, priority, depends_on) in [
+            ("dependency", Priority::Low, None),
+            ("first", Priority::Medium, None),
+            ("second", Priority::Medium, Some(1)),
+        ] {
+            let payload = CreateTodo {
+                priority: Some(priority),
+                depends_on,
+                ..create(text)
+            };
+            insert_task(&pool, payload, Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let medium = TaskQuery {
+            priority: Some(Priority::Medium),
+            ..Default::default()
+        };
+        let high = UpdateTodo {
+            priority: Some(Priority::High),
+            ..Default::default()
+        };
+        // listed ids outside the filter are left alone
+        let updated = update_tasks(&pool, &medium, SearchIndex::Like, Some(&[1, 2]), &high)
+            .await
+            .unwrap();
+        assert_eq!(updated, 1);
+        let none = update_tasks(&pool, &medium, SearchIndex::Like, Some(&[]), &high).await;
+        assert_eq!(none.unwrap(), 0);
+
+        // the blocked second task fails the batch, so the first stays open
+        let done = UpdateTodo {
+            done: Some(true),
+            ..Default::default()
+        };
+        let all = TaskQuery::default();
+        assert!(matches!(
+            update_tasks(&pool, &all, SearchIndex::Like, Some(&[2, 3]), &done).await,
+            Err(UpdateError::Blocked)
+        ));
+        let tasks = select_tasks(&pool, &all, SearchIndex::Like, false)
+            .await
+            .unwrap();
+        let state: Vec<_> = tasks.iter().map(|t| (t.priority, t.done)).collect();
+        assert_eq!(
+            state,
+            [
+                (Priority::Low, false),
+                (Priority::High, false),
+                (Priority::Medium, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_below_one_are_refused() {
+        for limit in [0, -1, -5, i64::MIN] {
+            assert!(matches!(
+                check_limit(Some(limit)),
+                Err(AppError::BadRequest(_))
+            ));
+        }
+        check_limit(Some(1)).unwrap();
+        check_limit(None).unwrap();
+    }
+
+    #[tokio::test]
+    async fn largest_limit_returns_everything() {
+        let pool = test_pool().await;
+        insert_task(&pool, create("only"), Priority::Low, None)
+            .await
+            .unwrap();
+        let params = TaskQuery {
+            limit: Some(i64::MAX),
+            ..Default::default()
+        };
+        let tasks = select_tasks(&pool, &params, SearchIndex::Like, false)
+            .await
+            .unwrap();
+        assert_eq!(tasks.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn clear_due_removes_the_due_date() {
+        let pool = test_pool().await;
+        let payload = CreateTodo {
+            due: Some(Utc::now()),
+            ..create("dated")
+        };
+        insert_task(&pool, payload, Priority::Low, None)
+            .await
+            .unwrap();
+        let payload = UpdateTodo {
+            due: Some(Utc::now()),
+            clear_due: true,
+            ..Default::default()
+        };
+        assert!(apply_update(&pool, 1, payload).await.unwrap());
+        let tasks = select_tasks(&pool, &TaskQuery::default(), SearchIndex::Like, false)
+            .await
+            .unwrap();
+        assert_eq!(tasks[0].due, None);
+    }
+
+    #[tokio::test]
+    async fn dependencies_refuse_cycles_and_missing_tasks() {
+        let pool = test_pool().await;
+        for (text, depends_on) in [("first", None), ("second", Some(1)), ("third", Some(2))] {
+            let payload = CreateTodo {
+                depends_on,
+                ..create(text)
+            };
+            insert_task(&pool, payload, Priority::Low, None)
+                .await
+                .unwrap();
+        }
+        let depend = |depends_on| UpdateTodo {
+            depends_on: Some(depends_on),
+            ..Default::default()
+        };
+        for (id, depends_on) in [(1, 3), (2, 2)] {
+            let result = apply_update(&pool, id, depend(Some(depends_on))).await;
+            assert!(matches!(result, Err(UpdateError::DependencyCycle)));
+        }
+        let result = apply_update(&pool, 3, depend(Some(99))).await;
+        assert!(matches!(result, Err(UpdateError::MissingDependency(99))));
+        assert!(!apply_update(&pool, 99, depend(Some(1))).await.unwrap());
+
+        let update: UpdateTodo = serde_json::from_str(r#"{"depends_on": null}"#).unwrap();
+        assert_eq!(update.depends_on, Some(None));
+        assert!(apply_update(&pool, 3, update).await.unwrap());
+        let tasks = select_tasks(&pool, &TaskQuery::default(), SearchIndex::Like, false)
+            .await
+            .unwrap();
+        let deps: Vec<_> = tasks.iter().map(|t| t.depends_on).collect();
+        assert_eq!(deps, [None, Some(1), None]);
+        let update: UpdateTodo = serde_json::from_str("{}").unwrap();
+        assert_eq!(update.depends_on, None);
+    }
+
+    #[test]
+    fn only_real_filters_narrow_a_deletion() {
+        assert!(!narrows_deletion(&TaskQuery::default()));
+        let done = TaskQuery {
+            done: Some(true),
+            ..Default::default()
+        };
+        assert!(narrows_deletion(&done));
+        refuse_paging(&done).unwrap();
+        for paged in [
+            TaskQuery {
+                limit: Some(5),
+                ..done
+            },
+            TaskQuery {
+                after_id: Some(0),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                refuse_paging(&paged),
+                Err(AppError::BadRequest(_))
+            ));
+        }
+    }
 }
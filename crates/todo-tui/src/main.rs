@@ -1,18 +1,31 @@
-use cli_log::{debug, error, init_cli_log};
-use color_eyre::eyre::Result;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use cli_log::{debug, error, init_cli_log, warn};
+use color_eyre::eyre::{Result, bail};
 use crossterm::event::{self, KeyModifiers};
 use crossterm::event::{Event, KeyCode};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
 use ratatui::layout::{Constraint, Direction, Flex, Layout, Rect};
 use ratatui::prelude::Alignment;
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
-use ratatui::widgets::{Block, Borders, Clear, List, ListItem, Paragraph};
+use ratatui::widgets::{
+    Bar, BarChart, BarGroup, Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Wrap,
+};
 use ratatui::{Frame, Terminal};
 use ratatui::{prelude::CrosstermBackend, widgets::ListState};
+use std::collections::HashSet;
 use std::io::stdout;
-use todo_common::{Filter, Priority, Task, TaskQuery};
+use std::path::PathBuf;
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, Instant};
+use todo_common::{
+    DateFormat, Filter, MatchMode, Priority, Task, TaskQuery, Urgency, format_when, parse_quick_add,
+};
 use tokio::sync::mpsc;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Default, PartialEq, Debug)]
 enum InputMode {
@@ -20,54 +33,864 @@ enum InputMode {
     Normal,
     Editing,
     Filter,
+    Search,
+    Summary,
+    Templates,
+    JumpToId,
+    Detail,
+    Confirm,
     Help,
 }
 
+#[derive(Debug)]
 enum Action {
-    Fetch(Filter),
-    Create(String, Filter),
-    Delete(i64, Filter),
-    Update(i64, Option<String>, Option<bool>, Option<Priority>, Filter),
+    Fetch(View),
+    FetchMore(View, i64),
+    FetchCompletedToday,
+    FetchTemplates,
+    /// Task counts for the filter popup
+    FetchCounts,
+    ApplyTemplate(i64, View),
+    Create(CreateTodo, View),
+    Delete(i64, View),
+    Toggle(i64, View),
+    Restore(i64, View),
+    Duplicate(i64, View),
+    Update(i64, UpdateTodo, View),
+    /// Applies the same changes to every listed task in one request
+    UpdateMany(Vec<i64>, UpdateTodo, View),
+}
+
+/// What the task list is currently showing. Actions carry it so the refetch
+/// that follows them matches what's on screen.
+#[derive(Clone, Debug, Default)]
+struct View {
+    filter: Filter,
+    search: Option<String>,
+    match_mode: MatchMode,
+    /// How many tasks the list holds, so a refetch brings back every loaded
+    /// page instead of snapping back to the first
+    loaded: usize,
+}
+
+/// Something held back until the user answers the prompt with y/n
+#[derive(Debug)]
+struct Confirmation {
+    prompt: String,
+    on_confirm: OnConfirm,
+}
+
+#[derive(Debug)]
+enum OnConfirm {
+    Send(Action),
+    /// Throws away the task being typed; answering n goes back to editing it
+    DiscardEdit,
+    /// Moves the shown tasks from the filter's priority to `target`, which
+    /// left/right change while the prompt is open
+    SetPriority {
+        ids: Vec<i64>,
+        from: Priority,
+        target: Priority,
+        view: View,
+    },
+}
+
+impl Confirmation {
+    fn set_priority(ids: Vec<i64>, from: Priority, view: View) -> Self {
+        let target = if from == Priority::High {
+            from.lowered()
+        } else {
+            from.raised()
+        };
+        Confirmation {
+            prompt: set_priority_prompt(ids.len(), target),
+            on_confirm: OnConfirm::SetPriority {
+                ids,
+                from,
+                target,
+                view,
+            },
+        }
+    }
+
+    /// Steps a `SetPriority` target up or down, skipping the priority the
+    /// tasks already have
+    fn cycle_target(&mut self, up: bool) {
+        let OnConfirm::SetPriority {
+            ids, from, target, ..
+        } = &mut self.on_confirm
+        else {
+            return;
+        };
+        let step = |p: Priority| if up { p.raised() } else { p.lowered() };
+        let next = step(*target);
+        let next = if next == *from { step(next) } else { next };
+        if next != *from {
+            *target = next;
+            self.prompt = set_priority_prompt(ids.len(), next);
+        }
+    }
+}
+
+fn set_priority_prompt(count: usize, target: Priority) -> String {
+    format!("Set {count} shown tasks to {target} priority? (left/right to change, y/n)")
 }
 
 enum TuiEvent {
-    TasksFetched(Vec<Task>),
+    TasksFetched(Vec<Task>, Option<i64>),
+    /// The server answered 304, so what's on screen is still current
+    Unchanged,
+    MoreTasksFetched(Vec<Task>, Option<i64>),
+    CompletedTodayFetched(Vec<Task>),
+    TemplatesFetched(Vec<Template>),
+    CountsFetched(FilterCounts),
+    /// The server answered but refused the request, with its explanation
+    Rejected(String),
+    /// How many changes are waiting in the offline queue
+    Queued(usize),
+    /// A request that never reached the server
+    Offline(String),
     Error(String),
 }
 
+/// `Offline` for an error that never reached the server, `Error` for
+/// everything else, including the server refusing the request
+fn error_event(e: Box<dyn std::error::Error + Send + Sync>) -> TuiEvent {
+    if is_offline(&*e) {
+        TuiEvent::Offline(e.to_string())
+    } else {
+        TuiEvent::Error(e.to_string())
+    }
+}
+
 #[derive(Default, Debug)]
 struct App {
     tasks: Vec<Task>,
     todo_state: ListState,
     filter_state: ListState,
     input: String,
+    /// What the inputs held when editing started, to tell if anything changed
+    original_input: String,
+    original_due_input: String,
     mode: InputMode,
     filter: Filter,
+    search: Option<String>,
+    search_input: String,
+    match_mode: MatchMode,
+    fuzzy: bool,
+    search_state: ListState,
+    /// Id typed into the jump popup, digits only
+    jump_input: String,
+    /// Shown under the jump input, for a rejected key or an id not in the list
+    jump_error: Option<String>,
     currently_editing_id: Option<i64>,
+    /// Version of the task being edited when editing started, so saving
+    /// doesn't overwrite a change another client made in the meantime
+    editing_version: i64,
+    due_input: String,
+    editing_due: bool,
+    /// Position in the focused input, counted in grapheme clusters so an emoji
+    /// with modifiers moves and deletes as one
+    cursor: usize,
+    input_error: Option<String>,
     priority: Priority,
+    help_state: ListState,
     help_size: usize,
     help_mode: InputMode,
+    next_cursor: Option<i64>,
+    loading_more: bool,
+    completed_today: Vec<Task>,
+    templates: Vec<Template>,
+    template_state: ListState,
+    /// Shown next to each entry of the filter popup once fetched
+    filter_counts: Option<FilterCounts>,
+    confirmation: Option<Confirmation>,
+    /// Showing Low/Medium/High columns instead of the flat list
+    board: bool,
+    board_column: Priority,
+    /// Deletes only move tasks to the server's trash, so the latest can be undone
+    last_deleted: Option<i64>,
+    refresh_interval: Option<Duration>,
+    /// Dims all but the top `focus_count` unfinished tasks by priority
+    focus: bool,
+    focus_count: usize,
+    /// Hides the Low/Medium/High bar chart above the status line
+    hide_distribution: bool,
+    last_fetch: Option<Instant>,
+    /// Whether the latest request reached the server, shown in the status line
+    last_request_ok: bool,
+    last_contact: Option<Instant>,
+    /// Why the server last refused a request, shown until the next key press
+    error_banner: Option<String>,
+    /// Changes made offline that haven't reached the server yet
+    queued: usize,
+    /// Pops up a desktop notification when a task gets within `DUE_SOON` of
+    /// its due date, once per task
+    notify_due: bool,
+    notified: HashSet<i64>,
+    date_format: DateFormat,
+    /// Last key press or server response, polling slows down once this is
+    /// `IDLE_AFTER` in the past
+    last_activity: Option<Instant>,
 }
 
-#[derive(serde::Serialize)]
+impl App {
+    /// Unfinished tasks that came due within `DUE_SOON` since the last call.
+    /// Tasks that leave the window, e.g. because their due date moved, can
+    /// be reported again later.
+    fn newly_due_soon(&mut self, now: DateTime<Utc>) -> Vec<&Task> {
+        let due_soon = |task: &Task| {
+            !task.done
+                && task
+                    .due
+                    .is_some_and(|due| due > now && due - now <= DUE_SOON)
+        };
+        let soon: HashSet<i64> = self
+            .tasks
+            .iter()
+            .filter(|t| due_soon(t))
+            .map(|t| t.id)
+            .collect();
+        let fresh: Vec<&Task> = self
+            .tasks
+            .iter()
+            .filter(|t| soon.contains(&t.id) && !self.notified.contains(&t.id))
+            .collect();
+        self.notified = soon;
+        fresh
+    }
+
+    fn view(&self) -> View {
+        View {
+            filter: self.filter,
+            search: self.search.clone(),
+            match_mode: self.match_mode,
+            loaded: self.tasks.len(),
+        }
+    }
+
+    /// The view after the filter or search changed, starting over from the
+    /// first page
+    fn first_page(&self) -> View {
+        View {
+            loaded: 0,
+            ..self.view()
+        }
+    }
+
+    /// Ranks the loaded tasks against the search input, best match first
+    fn fuzzy_matches(&self) -> Vec<&Task> {
+        let matcher = SkimMatcherV2::default();
+        let mut matches: Vec<(i64, &Task)> = self
+            .tasks
+            .iter()
+            .filter_map(|t| {
+                matcher
+                    .fuzzy_match(&t.text, &self.search_input)
+                    .map(|score| (score, t))
+            })
+            .collect();
+        matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        matches.into_iter().map(|(_, t)| t).collect()
+    }
+
+    fn has_unsaved_edit(&self) -> bool {
+        let typed = !self.input.is_empty() || !self.due_input.is_empty();
+        typed && (self.input != self.original_input || self.due_input != self.original_due_input)
+    }
+
+    fn focused_input(&mut self) -> &mut String {
+        if self.editing_due {
+            &mut self.due_input
+        } else {
+            &mut self.input
+        }
+    }
+
+    /// Puts the cursor after the last grapheme of whichever input is focused
+    fn cursor_to_end(&mut self) {
+        self.cursor = self.focused_input().graphemes(true).count();
+    }
+
+    /// A combining char joins the grapheme before it, so the cursor is
+    /// recounted rather than just stepped
+    fn insert_at_cursor(&mut self, c: char) {
+        let cursor = self.cursor;
+        let input = self.focused_input();
+        let at = byte_index(input, cursor);
+        input.insert(at, c);
+        self.cursor = input[..at + c.len_utf8()].graphemes(true).count();
+    }
+
+    fn remove_before_cursor(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.remove_at_cursor();
+        }
+    }
+
+    fn remove_at_cursor(&mut self) {
+        let cursor = self.cursor;
+        let input = self.focused_input();
+        let start = byte_index(input, cursor);
+        let end = byte_index(input, cursor + 1);
+        input.replace_range(start..end, "");
+    }
+
+    fn reset_input(&mut self) {
+        self.cursor = 0;
+        self.input.clear();
+        self.due_input.clear();
+        self.original_input.clear();
+        self.original_due_input.clear();
+        self.editing_due = false;
+        self.input_error = None;
+    }
+
+    /// Moves to the next (or previous) filter in the menu's order, wrapping
+    /// around. A priority filter for another priority than the current one
+    /// counts as not in the menu, so cycling starts over from the top.
+    fn cycle_filter(&mut self, forward: bool) {
+        let filters = get_menu_filters(self.priority);
+        let next = match filters.iter().position(|&f| f == self.filter) {
+            Some(i) if forward => (i + 1) % filters.len(),
+            Some(i) => (i + filters.len() - 1) % filters.len(),
+            None => 0,
+        };
+        self.filter = filters[next];
+    }
+
+    /// Columns needed for the widest id in the list, so ids can be
+    /// right-aligned and `1` lines up with `100`
+    fn id_width(&self) -> usize {
+        self.tasks
+            .iter()
+            .map(|t| t.id)
+            .max()
+            .map_or(1, |id| id.to_string().len())
+    }
+
+    /// Ids of the tasks focus mode keeps bright, or `None` when it's off. Ties
+    /// in priority go to whichever comes first in the list.
+    fn focused_ids(&self) -> Option<HashSet<i64>> {
+        if !self.focus {
+            return None;
+        }
+        let mut open: Vec<&Task> = self.tasks.iter().filter(|t| !t.done).collect();
+        open.sort_by_key(|t| std::cmp::Reverse(t.priority.weight()));
+        Some(open.iter().take(self.focus_count).map(|t| t.id).collect())
+    }
+
+    /// Indices into `tasks` of the ones in a kanban column, in list order
+    fn column_indices(&self, priority: Priority) -> Vec<usize> {
+        (0..self.tasks.len())
+            .filter(|&i| self.tasks[i].priority == priority)
+            .collect()
+    }
+
+    /// Focuses a kanban column, selecting its first task. The selection stays
+    /// in `todo_state` so every other key works on it unchanged.
+    fn focus_column(&mut self, priority: Priority) {
+        self.board_column = priority;
+        let first = self.column_indices(priority).first().copied();
+        self.todo_state.select(first);
+    }
+
+    /// Moves the selection within the focused kanban column, wrapping around
+    fn step_in_column(&mut self, down: bool) {
+        let column = self.column_indices(self.board_column);
+        if column.is_empty() {
+            return;
+        }
+        let position = self
+            .todo_state
+            .selected()
+            .and_then(|i| column.iter().position(|&c| c == i));
+        let next = match position {
+            Some(p) if down => (p + 1) % column.len(),
+            Some(p) => (p + column.len() - 1) % column.len(),
+            None => 0,
+        };
+        self.todo_state.select(Some(column[next]));
+    }
+
+    /// Swaps in a freshly fetched list, keeping the same task selected if it's
+    /// still there, otherwise the nearest valid row
+    fn replace_tasks(&mut self, tasks: Vec<Task>) {
+        let selected = self.todo_state.selected();
+        let selected_id = selected.and_then(|i| self.tasks.get(i)).map(|t| t.id);
+        self.tasks = tasks;
+
+        let index = selected_id
+            .and_then(|id| self.tasks.iter().position(|t| t.id == id))
+            .or_else(|| selected.map(|i| i.min(self.tasks.len().saturating_sub(1))));
+        if self.tasks.is_empty() {
+            self.todo_state.select(None);
+        } else {
+            self.todo_state.select(index);
+        }
+    }
+}
+
+/// What's remembered between runs, stored in the user's config directory
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+#[serde(default)]
+struct SavedState {
+    filter: Filter,
+    /// `None` until a priority has been picked, then the config's
+    /// `default_priority` no longer applies
+    priority: Option<Priority>,
+}
+
+impl SavedState {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("todo").join("state.toml"))
+    }
+
+    /// Missing or unreadable state just means starting from the defaults
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Settings read once at startup from `config.toml` in the user's config
+/// directory. Every field is optional, and the `TODO_TUI_*` variables
+/// override the file where both are set.
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    server_url: String,
+    /// Sent as a bearer token with every request
+    token: Option<String>,
+    poll_ms: u64,
+    /// Used after a few seconds without input
+    idle_poll_ms: u64,
+    /// Refetch the list every so often, 0 to only refresh on `r` or after
+    /// changes
+    refresh_secs: u64,
+    focus_count: usize,
+    notify: bool,
+    date_format: DateFormat,
+    /// Priority for new tasks until one has been picked
+    default_priority: Priority,
+    theme: Theme,
+    keys: Keys,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            server_url: "http://localhost:3000".to_string(),
+            token: None,
+            poll_ms: DEFAULT_POLL_INTERVAL.as_millis() as u64,
+            idle_poll_ms: DEFAULT_IDLE_POLL_INTERVAL.as_millis() as u64,
+            refresh_secs: 0,
+            focus_count: DEFAULT_FOCUS_COUNT,
+            notify: false,
+            date_format: DateFormat::default(),
+            default_priority: Priority::default(),
+            theme: Theme::default(),
+            keys: Keys::default(),
+        }
+    }
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("todo").join("config.toml"))
+    }
+
+    /// A missing file means all defaults, but one that's there and doesn't
+    /// parse, or settings that can't work, are an error rather than silently
+    /// ignored
+    fn load() -> Result<Self> {
+        let mut config: Config = match Self::path().map(std::fs::read_to_string) {
+            Some(Ok(contents)) => toml::from_str(&contents)?,
+            Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => Config::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok());
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides settings with the `TODO_TUI_*` variables `lookup` finds,
+    /// skipping empty ones
+    fn apply_env(&mut self, lookup: impl Fn(&str) -> Option<String>) {
+        let var = |name| lookup(name).filter(|v| !v.is_empty());
+        if let Some(url) = var("TODO_TUI_SERVER_URL") {
+            self.server_url = url;
+        }
+        if let Some(token) = var("TODO_TUI_TOKEN") {
+            self.token = Some(token);
+        }
+        if let Some(ms) = var("TODO_TUI_POLL_MS").and_then(|ms| ms.parse().ok()) {
+            self.poll_ms = ms;
+        }
+        if let Some(ms) = var("TODO_TUI_IDLE_POLL_MS").and_then(|ms| ms.parse().ok()) {
+            self.idle_poll_ms = ms;
+        }
+        if let Some(secs) = var("TODO_TUI_REFRESH_SECS").and_then(|secs| secs.parse().ok()) {
+            self.refresh_secs = secs;
+        }
+        if let Some(n) = var("TODO_TUI_FOCUS_COUNT").and_then(|n| n.parse().ok()) {
+            self.focus_count = n;
+        }
+        if let Some(notify) = var("TODO_TUI_NOTIFY") {
+            self.notify = notify == "1";
+        }
+        if let Some(strike) = var("TODO_TUI_STRIKE_DONE") {
+            self.theme.strike_done = strike != "0";
+        }
+        if let Some(format) = var("TODO_TUI_DATE_FORMAT").and_then(|f| f.parse().ok()) {
+            self.date_format = format;
+        }
+        if let Some(priority) = var("TODO_TUI_DEFAULT_PRIORITY").and_then(|p| p.parse().ok()) {
+            self.default_priority = priority;
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(token) = &self.token
+            && reqwest::header::HeaderValue::from_str(&format!("Bearer {token}")).is_err()
+        {
+            bail!("the token can't be sent in an HTTP header, check it for stray characters");
+        }
+        // same order as Keys::bindings
+        let names = [
+            "quit", "add", "edit", "delete", "refresh", "search", "filter",
+        ];
+        let keys = self.keys.bindings().map(|(key, _)| key);
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(j) = keys[i + 1..].iter().position(|k| k == key) {
+                bail!(
+                    "{key:?} is bound to both {} and {}",
+                    names[i],
+                    names[i + 1 + j]
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Set from the file at startup, defaults before that
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Colours by name (`"green"`, `"lightblue"`) or hex (`"#88c0d0"`)
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Theme {
+    #[serde(deserialize_with = "color_by_name")]
+    done: Color,
+    #[serde(deserialize_with = "color_by_name")]
+    pending: Color,
+    /// Cross out the text of done tasks too
+    strike_done: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            done: Color::Green,
+            pending: Color::Yellow,
+            strike_done: true,
+        }
+    }
+}
+
+fn color_by_name<'de, D>(deserializer: D) -> Result<Color, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name: String = serde::Deserialize::deserialize(deserializer)?;
+    name.parse()
+        .map_err(|_| serde::de::Error::custom(format!("unknown colour {name:?}")))
+}
+
+/// Normal mode keys that can be rebound. A default key that's been rebound
+/// does nothing, unless it's another binding's new key.
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Keys {
+    quit: char,
+    add: char,
+    edit: char,
+    delete: char,
+    refresh: char,
+    search: char,
+    filter: char,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys {
+            quit: 'q',
+            add: 'i',
+            edit: 'e',
+            delete: 'd',
+            refresh: 'r',
+            search: '/',
+            filter: 'f',
+        }
+    }
+}
+
+impl Keys {
+    /// Maps a pressed key to the default key of the binding it triggers, so
+    /// the key handling can keep matching on the defaults
+    fn resolve(&self, code: KeyCode) -> KeyCode {
+        let KeyCode::Char(c) = code else {
+            return code;
+        };
+        let bindings = self.bindings();
+        if let Some(&(_, default)) = bindings.iter().find(|&&(key, _)| key == c) {
+            KeyCode::Char(default)
+        } else if bindings
+            .iter()
+            .any(|&(key, default)| default == c && key != c)
+        {
+            KeyCode::Null
+        } else {
+            code
+        }
+    }
+
+    /// Each binding's key paired with its default
+    fn bindings(&self) -> [(char, char); 7] {
+        let defaults = Keys::default();
+        [
+            (self.quit, defaults.quit),
+            (self.add, defaults.add),
+            (self.edit, defaults.edit),
+            (self.delete, defaults.delete),
+            (self.refresh, defaults.refresh),
+            (self.search, defaults.search),
+            (self.filter, defaults.filter),
+        ]
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct CreateTodo {
     text: String,
+    priority: Option<Priority>,
+    due: Option<DateTime<Utc>>,
+    #[serde(skip_deserializing, default = "tui_source")]
+    source: &'static str,
 }
 
-#[derive(serde::Serialize)]
+fn tui_source() -> &'static str {
+    "tui"
+}
+
+/// A saved set of tasks the server creates in one go, only what the
+/// templates popup shows
+#[derive(serde::Deserialize, Debug)]
+struct Template {
+    id: i64,
+    name: String,
+    tasks: Vec<serde::de::IgnoredAny>,
+}
+
+/// The parts of `GET /todos/stats` needed to count what each filter matches
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+struct FilterCounts {
+    total: i64,
+    done: i64,
+    low: i64,
+    medium: i64,
+    high: i64,
+}
+
+impl FilterCounts {
+    /// Across every task on the server, not just the ones loaded
+    fn matching(&self, filter: Filter) -> i64 {
+        match filter {
+            Filter::All => self.total,
+            Filter::Todo => self.total - self.done,
+            Filter::Done => self.done,
+            Filter::Priority(Priority::Low) => self.low,
+            Filter::Priority(Priority::Medium) => self.medium,
+            Filter::Priority(Priority::High) => self.high,
+        }
+    }
+}
+
+/// What the server sends back with a refused request
+#[derive(serde::Deserialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
 struct UpdateTodo {
     text: Option<String>,
     done: Option<bool>,
     priority: Option<Priority>,
+    due: Option<DateTime<Utc>>,
+    /// Removes the due date, the only way to since `None` leaves it as is
+    #[serde(default)]
+    clear_due: bool,
+    label: Option<String>,
+    version: Option<i64>,
+}
+
+/// A change made while the server couldn't be reached
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[serde(tag = "kind")]
+enum Pending {
+    Create { task: CreateTodo },
+    Update { id: i64, changes: UpdateTodo },
+    Delete { id: i64 },
+}
+
+/// Changes waiting for the server to come back, stored in the user's data
+/// directory so they survive a restart
+#[derive(serde::Serialize, serde::Deserialize, Default, Debug)]
+#[serde(default)]
+struct OfflineQueue {
+    pending: Vec<Pending>,
+}
+
+impl OfflineQueue {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("todo").join("queue.toml"))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path().ok_or("no data directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn push(&mut self, change: Pending) {
+        debug!("queueing {change:?} until the server is back");
+        self.pending.push(change);
+        if let Err(e) = self.save() {
+            error!("failed to save offline queue: {e}");
+        }
+    }
+
+    /// Sends queued changes oldest first, stopping at the first one that
+    /// still can't reach the server. Ones the server refuses, e.g. an update
+    /// to a task that was changed elsewhere since, are logged and dropped
+    /// since sending them again won't help. Returns how many were dropped.
+    async fn replay(&mut self) -> usize {
+        let dropped = self
+            .replay_with(async |change| match change {
+                Pending::Create { task } => create_task(task).await,
+                Pending::Update { id, changes } => update_task(*id, changes)
+                    .await
+                    .map(|ok| (!ok).then(|| format!("task {id} was changed elsewhere"))),
+                Pending::Delete { id } => delete_task(*id).await.map(|()| None),
+            })
+            .await;
+        if let Err(e) = self.save() {
+            error!("failed to save offline queue: {e}");
+        }
+        dropped
+    }
+
+    /// `replay` with `send` standing in for the server. It answers
+    /// `Ok(Some(reason))` for a change the server turned down.
+    async fn replay_with(
+        &mut self,
+        mut send: impl AsyncFnMut(
+            &Pending,
+        )
+            -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>>,
+    ) -> usize {
+        let mut dropped = 0;
+        while !self.pending.is_empty() {
+            let change = self.pending.remove(0);
+            match send(&change).await {
+                Ok(None) => debug!("replayed {change:?}"),
+                Ok(Some(reason)) => {
+                    warn!("dropping queued {change:?}: {reason}");
+                    dropped += 1;
+                }
+                Err(e) if is_offline(&*e) => {
+                    self.pending.insert(0, change);
+                    break;
+                }
+                Err(e) => {
+                    warn!("dropping queued {change:?}: {e}");
+                    dropped += 1;
+                }
+            }
+        }
+        dropped
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenvy::dotenv()?;
 
+    CONFIG
+        .set(Config::load()?)
+        .expect("config is only loaded once");
+    let config = config();
+    let poll_interval = Duration::from_millis(config.poll_ms);
+    let idle_poll_interval = Duration::from_millis(config.idle_poll_ms).max(poll_interval);
+    // auto-refresh is opt-in, 0 means only refresh on `r` or after changes
+    let refresh_interval =
+        (config.refresh_secs > 0).then(|| Duration::from_secs(config.refresh_secs));
+
+    let saved = SavedState::load();
+    // ETag of the last first page fetched, sent back so an unchanged list
+    // comes back as 304 instead of being downloaded and redrawn again
+    let mut etag = None;
+    let mut queue = OfflineQueue::load();
     let mut app = {
+        let view = View {
+            filter: saved.filter,
+            ..Default::default()
+        };
+        let initial = fetch_tasks(&view, None, &mut etag).await;
+        let last_request_ok = !matches!(&initial, Err(e) if is_offline(&**e));
+        let (tasks, next_cursor) = initial.ok().flatten().unwrap_or_default();
         App {
-            tasks: fetch_tasks(Filter::default()).await.unwrap_or_default(),
+            tasks,
+            filter: saved.filter,
+            priority: saved.priority.unwrap_or(config.default_priority),
+            next_cursor,
+            refresh_interval,
+            focus_count: config.focus_count,
+            last_fetch: Some(Instant::now()),
+            last_request_ok,
+            last_contact: last_request_ok.then(Instant::now),
+            queued: queue.pending.len(),
+            // desktop notifications are opt-in, not everyone wants popups
+            notify_due: config.notify,
+            date_format: config.date_format,
             ..Default::default()
         }
     };
@@ -77,37 +900,226 @@ async fn main() -> Result<()> {
     tokio::spawn(async move {
         while let Some(action) = action_rx.recv().await {
             match action {
-                Action::Fetch(filter) => match fetch_tasks(filter).await {
-                    Ok(tasks) => event_tx.send(TuiEvent::TasksFetched(tasks)).unwrap(),
-                    Err(e) => event_tx.send(TuiEvent::Error(e.to_string())).unwrap(),
+                Action::Fetch(view) => {
+                    let mut result = fetch_tasks(&view, None, &mut etag).await;
+                    if result.is_ok() && !queue.pending.is_empty() {
+                        // the server is back, catch it up before showing the list
+                        flush_queue(&mut queue, &event_tx).await;
+                        result = fetch_tasks(&view, None, &mut etag).await;
+                    }
+                    match result {
+                        Ok(Some((tasks, cursor))) => event_tx
+                            .send(TuiEvent::TasksFetched(tasks, cursor))
+                            .unwrap(),
+                        Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                        Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    }
+                }
+                Action::FetchMore(view, after_id) => {
+                    match fetch_tasks(&view, Some(after_id), &mut None).await {
+                        Ok(Some((tasks, cursor))) => event_tx
+                            .send(TuiEvent::MoreTasksFetched(tasks, cursor))
+                            .unwrap(),
+                        Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                        Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    }
+                }
+                Action::FetchCompletedToday => match fetch_completed_today().await {
+                    Ok(tasks) => event_tx
+                        .send(TuiEvent::CompletedTodayFetched(tasks))
+                        .unwrap(),
+                    Err(e) => event_tx.send(error_event(e)).unwrap(),
+                },
+                Action::FetchCounts => match fetch_counts().await {
+                    Ok(counts) => event_tx.send(TuiEvent::CountsFetched(counts)).unwrap(),
+                    Err(e) => event_tx.send(error_event(e)).unwrap(),
+                },
+                Action::FetchTemplates => match fetch_templates().await {
+                    Ok(templates) => event_tx
+                        .send(TuiEvent::TemplatesFetched(templates))
+                        .unwrap(),
+                    Err(e) => event_tx.send(error_event(e)).unwrap(),
                 },
-                Action::Create(text, filter) => {
-                    if let Err(e) = create_task(text).await {
-                        event_tx.send(TuiEvent::Error(e.to_string())).unwrap();
+                Action::ApplyTemplate(id, view) => match apply_template(id).await {
+                    Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    Ok(Some(reason)) => event_tx.send(TuiEvent::Rejected(reason)).unwrap(),
+                    Ok(None) => match fetch_tasks(&view, None, &mut etag).await {
+                        Ok(Some((tasks, cursor))) => event_tx
+                            .send(TuiEvent::TasksFetched(tasks, cursor))
+                            .unwrap(),
+                        Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                        Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    },
+                },
+                Action::Create(payload, view) => {
+                    if !queue.pending.is_empty() {
+                        // older offline changes go first to keep them in order
+                        queue.push(Pending::Create { task: payload });
+                        flush_queue(&mut queue, &event_tx).await;
+                    } else {
+                        match create_task(&payload).await {
+                            Err(e) if is_offline(&*e) => {
+                                event_tx.send(TuiEvent::Offline(e.to_string())).unwrap();
+                                queue.push(Pending::Create { task: payload });
+                                event_tx
+                                    .send(TuiEvent::Queued(queue.pending.len()))
+                                    .unwrap();
+                                continue;
+                            }
+                            Err(e) => {
+                                event_tx.send(error_event(e)).unwrap();
+                                continue;
+                            }
+                            Ok(Some(reason)) => {
+                                event_tx.send(TuiEvent::Rejected(reason)).unwrap();
+                                continue;
+                            }
+                            Ok(None) => {}
+                        }
+                    }
+                    match fetch_tasks(&view, None, &mut etag).await {
+                        Ok(Some((tasks, cursor))) => event_tx
+                            .send(TuiEvent::TasksFetched(tasks, cursor))
+                            .unwrap(),
+                        Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                        Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    }
+                }
+                Action::Delete(id, view) => {
+                    if !queue.pending.is_empty() {
+                        queue.push(Pending::Delete { id });
+                        flush_queue(&mut queue, &event_tx).await;
+                    } else {
+                        match delete_task(id).await {
+                            Err(e) if is_offline(&*e) => {
+                                event_tx.send(TuiEvent::Offline(e.to_string())).unwrap();
+                                queue.push(Pending::Delete { id });
+                                event_tx
+                                    .send(TuiEvent::Queued(queue.pending.len()))
+                                    .unwrap();
+                                continue;
+                            }
+                            Err(e) => {
+                                event_tx.send(error_event(e)).unwrap();
+                                continue;
+                            }
+                            Ok(()) => {}
+                        }
+                    }
+                    match fetch_tasks(&view, None, &mut etag).await {
+                        Ok(Some((tasks, cursor))) => event_tx
+                            .send(TuiEvent::TasksFetched(tasks, cursor))
+                            .unwrap(),
+                        Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                        Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    }
+                }
+                Action::Toggle(id, view) => {
+                    if let Err(e) = toggle_task(id).await {
+                        event_tx.send(error_event(e)).unwrap();
                     } else {
-                        match fetch_tasks(filter).await {
-                            Ok(tasks) => event_tx.send(TuiEvent::TasksFetched(tasks)).unwrap(),
-                            Err(e) => event_tx.send(TuiEvent::Error(e.to_string())).unwrap(),
+                        match fetch_tasks(&view, None, &mut etag).await {
+                            Ok(Some((tasks, cursor))) => event_tx
+                                .send(TuiEvent::TasksFetched(tasks, cursor))
+                                .unwrap(),
+                            Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                            Err(e) => event_tx.send(error_event(e)).unwrap(),
                         }
                     }
                 }
-                Action::Delete(id, filter) => {
-                    if let Err(e) = delete_task(id).await {
-                        event_tx.send(TuiEvent::Error(e.to_string())).unwrap();
+                Action::Restore(id, view) => {
+                    if let Err(e) = restore_task(id).await {
+                        event_tx.send(error_event(e)).unwrap();
                     } else {
-                        match fetch_tasks(filter).await {
-                            Ok(tasks) => event_tx.send(TuiEvent::TasksFetched(tasks)).unwrap(),
-                            Err(e) => event_tx.send(TuiEvent::Error(e.to_string())).unwrap(),
+                        match fetch_tasks(&view, None, &mut etag).await {
+                            Ok(Some((tasks, cursor))) => event_tx
+                                .send(TuiEvent::TasksFetched(tasks, cursor))
+                                .unwrap(),
+                            Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                            Err(e) => event_tx.send(error_event(e)).unwrap(),
                         }
                     }
                 }
-                Action::Update(id, text, done, priority, filter) => {
-                    if let Err(e) = update_task(id, text, done, priority).await {
-                        event_tx.send(TuiEvent::Error(e.to_string())).unwrap();
+                Action::Duplicate(id, view) => {
+                    if let Err(e) = duplicate_task(id).await {
+                        event_tx.send(error_event(e)).unwrap();
                     } else {
-                        match fetch_tasks(filter).await {
-                            Ok(tasks) => event_tx.send(TuiEvent::TasksFetched(tasks)).unwrap(),
-                            Err(e) => event_tx.send(TuiEvent::Error(e.to_string())).unwrap(),
+                        match fetch_tasks(&view, None, &mut etag).await {
+                            Ok(Some((tasks, cursor))) => event_tx
+                                .send(TuiEvent::TasksFetched(tasks, cursor))
+                                .unwrap(),
+                            Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                            Err(e) => event_tx.send(error_event(e)).unwrap(),
+                        }
+                    }
+                }
+                Action::UpdateMany(ids, payload, view) => {
+                    // all or none, so there's no half-updated view to explain
+                    if let Err(e) = update_many(&view, &ids, &payload).await {
+                        event_tx.send(error_event(e)).unwrap();
+                    }
+                    match fetch_tasks(&view, None, &mut etag).await {
+                        Ok(Some((tasks, cursor))) => event_tx
+                            .send(TuiEvent::TasksFetched(tasks, cursor))
+                            .unwrap(),
+                        Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                        Err(e) => event_tx.send(error_event(e)).unwrap(),
+                    }
+                }
+                Action::Update(id, mut payload, view) => {
+                    if !queue.pending.is_empty() {
+                        queue.push(Pending::Update {
+                            id,
+                            changes: payload,
+                        });
+                        flush_queue(&mut queue, &event_tx).await;
+                        match fetch_tasks(&view, None, &mut etag).await {
+                            Ok(Some((tasks, cursor))) => event_tx
+                                .send(TuiEvent::TasksFetched(tasks, cursor))
+                                .unwrap(),
+                            Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                            Err(e) => event_tx.send(error_event(e)).unwrap(),
+                        }
+                        continue;
+                    }
+                    let mut result = update_task(id, &payload).await;
+                    if let Err(e) = &result
+                        && is_offline(&**e)
+                    {
+                        event_tx.send(TuiEvent::Offline(e.to_string())).unwrap();
+                        queue.push(Pending::Update {
+                            id,
+                            changes: payload,
+                        });
+                        event_tx
+                            .send(TuiEvent::Queued(queue.pending.len()))
+                            .unwrap();
+                        continue;
+                    }
+                    if matches!(result, Ok(false)) {
+                        // another client changed the task first, so reapply
+                        // just our fields on top of its latest version, once
+                        etag = None;
+                        if let Ok(Some((tasks, _))) = fetch_tasks(&view, None, &mut etag).await
+                            && let Some(task) = tasks.iter().find(|t| t.id == id)
+                        {
+                            payload.version = Some(task.version);
+                            result = update_task(id, &payload).await;
+                        }
+                    }
+                    if let Err(e) = result {
+                        event_tx.send(error_event(e)).unwrap();
+                    } else {
+                        if matches!(result, Ok(false)) {
+                            let message = "task was changed elsewhere, reloaded it";
+                            event_tx.send(TuiEvent::Error(message.to_string())).unwrap();
+                        }
+                        match fetch_tasks(&view, None, &mut etag).await {
+                            Ok(Some((tasks, cursor))) => event_tx
+                                .send(TuiEvent::TasksFetched(tasks, cursor))
+                                .unwrap(),
+                            Ok(None) => event_tx.send(TuiEvent::Unchanged).unwrap(),
+                            Err(e) => event_tx.send(error_event(e)).unwrap(),
                         }
                     }
                 }
@@ -124,63 +1136,327 @@ async fn main() -> Result<()> {
 
     loop {
         while let Ok(event) = event_rx.try_recv() {
+            app.last_activity = Some(Instant::now());
+            match &event {
+                TuiEvent::Offline(_) => app.last_request_ok = false,
+                // says nothing about whether the server is reachable
+                TuiEvent::Queued(_) => {}
+                _ => {
+                    app.last_request_ok = true;
+                    app.last_contact = Some(Instant::now());
+                }
+            }
             match event {
-                TuiEvent::TasksFetched(tasks) => app.tasks = tasks,
-                TuiEvent::Error(msg) => error!("event error: {msg}"),
+                TuiEvent::TasksFetched(tasks, cursor) => {
+                    app.last_fetch = Some(Instant::now());
+                    app.replace_tasks(tasks);
+                    app.next_cursor = cursor;
+                    app.loading_more = false;
+                }
+                TuiEvent::Unchanged => {
+                    app.last_fetch = Some(Instant::now());
+                    app.loading_more = false;
+                }
+                TuiEvent::MoreTasksFetched(tasks, cursor) => {
+                    app.tasks.extend(tasks);
+                    app.next_cursor = cursor;
+                    app.loading_more = false;
+                }
+                TuiEvent::CompletedTodayFetched(tasks) => app.completed_today = tasks,
+                TuiEvent::CountsFetched(counts) => app.filter_counts = Some(counts),
+                TuiEvent::TemplatesFetched(templates) => {
+                    app.template_state
+                        .select((!templates.is_empty()).then_some(0));
+                    app.templates = templates;
+                }
+                TuiEvent::Rejected(reason) => {
+                    error!("request rejected: {reason}");
+                    app.error_banner = Some(reason);
+                }
+                TuiEvent::Queued(count) => app.queued = count,
+                TuiEvent::Offline(msg) | TuiEvent::Error(msg) => {
+                    app.loading_more = false;
+                    error!("event error: {msg}");
+                }
+            }
+        }
+        if let Some(interval) = app.refresh_interval
+            && app.last_fetch.is_none_or(|at| at.elapsed() >= interval)
+        {
+            app.last_fetch = Some(Instant::now());
+            action_tx.send(Action::Fetch(app.view()))?;
+        }
+        if app.notify_due {
+            for task in app.newly_due_soon(Utc::now()) {
+                notify_due_soon(task);
             }
         }
+
         terminal.draw(|f| ui(f, &mut app))?;
 
-        if event::poll(std::time::Duration::from_millis(50))?
+        let idle = !app.loading_more
+            && app
+                .last_activity
+                .is_none_or(|at| at.elapsed() >= IDLE_AFTER);
+        let poll = if idle {
+            idle_poll_interval
+        } else {
+            poll_interval
+        };
+        if event::poll(poll)?
             && let Event::Key(key) = event::read()?
         {
+            app.last_activity = Some(Instant::now());
+            app.error_banner = None;
             match app.mode {
-                InputMode::Normal => match key.code {
+                InputMode::Normal => match config.keys.resolve(key.code) {
                     KeyCode::Char('q') => break,
+                    // kanban navigation, ahead of the list keys it shadows
+                    KeyCode::Char('h') | KeyCode::Left if app.board => {
+                        app.focus_column(app.board_column.lowered());
+                    }
+                    KeyCode::Char('l') | KeyCode::Right if app.board => {
+                        app.focus_column(app.board_column.raised());
+                    }
+                    KeyCode::Char('k') | KeyCode::Up if app.board => app.step_in_column(false),
+                    KeyCode::Char('j') | KeyCode::Down if app.board => app.step_in_column(true),
+                    // moves the task to the next column over, focus follows it
+                    KeyCode::Char(c @ ('H' | 'L')) if app.board => {
+                        if let Some(index) = app.todo_state.selected()
+                            && let Some(task) = app.tasks.get(index)
+                        {
+                            let priority = if c == 'H' {
+                                task.priority.lowered()
+                            } else {
+                                task.priority.raised()
+                            };
+                            if priority != task.priority {
+                                app.board_column = priority;
+                                action_tx.send(Action::Update(
+                                    task.id,
+                                    UpdateTodo {
+                                        priority: Some(priority),
+                                        version: Some(task.version),
+                                        ..Default::default()
+                                    },
+                                    app.view(),
+                                ))?;
+                            }
+                        }
+                    }
                     KeyCode::Char('r') => {
-                        action_tx.send(Action::Fetch(app.filter))?;
+                        action_tx.send(Action::Fetch(app.view()))?;
                     }
+                    KeyCode::Char('F') => app.focus = !app.focus,
+                    KeyCode::Char('p') => app.hide_distribution = !app.hide_distribution,
                     KeyCode::Char('i') => app.mode = InputMode::Editing,
                     KeyCode::Char('e') => {
                         if let Some(index) = app.todo_state.selected()
                             && let Some(task) = app.tasks.get(index)
                         {
                             app.currently_editing_id = Some(task.id);
+                            app.editing_version = task.version;
                             app.mode = InputMode::Editing;
                             app.input.push_str(&task.text); // append task text
+                            if let Some(due) = task.due {
+                                app.due_input = due
+                                    .with_timezone(&Local)
+                                    .format(DUE_INPUT_FORMAT)
+                                    .to_string();
+                            }
+                            app.cursor_to_end();
+                            app.original_input = app.input.clone();
+                            app.original_due_input = app.due_input.clone();
                             debug!("current editing id: {}", app.currently_editing_id.unwrap());
                         }
                     }
                     KeyCode::Char('d') => {
                         if let Some(index) = app.todo_state.selected()
                             && let Some(task) = app.tasks.get(index)
-                            && let Err(e) = action_tx.send(Action::Delete(task.id, app.filter))
                         {
-                            error!("failed to send delete action: {e}");
+                            app.last_deleted = Some(task.id);
+                            if let Err(e) = action_tx.send(Action::Delete(task.id, app.view())) {
+                                error!("failed to send delete action: {e}");
+                            }
+                        }
+                    }
+                    KeyCode::Char('u') => {
+                        if let Some(id) = app.last_deleted.take()
+                            && let Err(e) = action_tx.send(Action::Restore(id, app.view()))
+                        {
+                            error!("failed to send restore action: {e}");
                         }
                     }
+                    KeyCode::Char('y') => {
+                        if let Some(index) = app.todo_state.selected()
+                            && let Some(task) = app.tasks.get(index)
+                            && let Err(e) = action_tx.send(Action::Duplicate(task.id, app.view()))
+                        {
+                            error!("failed to send duplicate action: {e}");
+                        }
+                    }
+                    // cycles red -> green -> blue -> none, an empty label clears it
+                    KeyCode::Char('c') => {
+                        if let Some(index) = app.todo_state.selected()
+                            && let Some(task) = app.tasks.get(index)
+                        {
+                            let next = match task.label.as_deref() {
+                                None => 0,
+                                Some(label) => {
+                                    LABELS.iter().position(|&l| l == label).map_or(0, |i| i + 1)
+                                }
+                            };
+                            let label = LABELS.get(next).copied().unwrap_or_default();
+                            let payload = UpdateTodo {
+                                label: Some(label.to_string()),
+                                version: Some(task.version),
+                                ..Default::default()
+                            };
+                            if let Err(e) =
+                                action_tx.send(Action::Update(task.id, payload, app.view()))
+                            {
+                                error!("failed to send label (update) action: {e}");
+                            }
+                        }
+                    }
+                    KeyCode::Char('/') => {
+                        app.search_input = app.search.clone().unwrap_or_default();
+                        app.search_state.select(Some(0));
+                        app.mode = InputMode::Search;
+                    }
+                    KeyCode::Char(':' | '#') if !app.tasks.is_empty() => {
+                        app.jump_input.clear();
+                        app.jump_error = None;
+                        app.mode = InputMode::JumpToId;
+                    }
                     KeyCode::Char('f') => {
                         app.mode = InputMode::Filter;
                         app.filter_state.select(Some(0));
+                        action_tx.send(Action::FetchCounts)?;
+                    }
+                    KeyCode::Tab | KeyCode::BackTab => {
+                        app.cycle_filter(key.code == KeyCode::Tab);
+                        debug!("setting filter to {}", app.filter);
+                        action_tx.send(Action::Fetch(app.first_page()))?;
                     }
-                    KeyCode::Char('h') => {
+                    KeyCode::Char('h') | KeyCode::Char('?') => {
+                        app.help_state.select(Some(0));
                         app.mode = InputMode::Help;
                     }
-                    KeyCode::Enter => {
+                    // marks the shown open tasks done in one request, or reopens
+                    // what's shown if it's all already done. Tasks on pages
+                    // that haven't been loaded are left alone.
+                    KeyCode::Char('A') if !app.tasks.is_empty() => {
+                        let open: Vec<i64> = app
+                            .tasks
+                            .iter()
+                            .filter(|t| !t.done && !t.blocked)
+                            .map(|t| t.id)
+                            .collect();
+                        let confirmation = if !open.is_empty() {
+                            Confirmation {
+                                prompt: format!("Mark {} shown tasks done? (y/n)", open.len()),
+                                on_confirm: OnConfirm::Send(Action::UpdateMany(
+                                    open,
+                                    UpdateTodo {
+                                        done: Some(true),
+                                        ..Default::default()
+                                    },
+                                    app.view(),
+                                )),
+                            }
+                        } else {
+                            let ids: Vec<i64> = app.tasks.iter().map(|t| t.id).collect();
+                            Confirmation {
+                                prompt: format!("Reopen all {} shown tasks? (y/n)", ids.len()),
+                                on_confirm: OnConfirm::Send(Action::UpdateMany(
+                                    ids,
+                                    UpdateTodo {
+                                        done: Some(false),
+                                        ..Default::default()
+                                    },
+                                    app.view(),
+                                )),
+                            }
+                        };
+                        app.confirmation = Some(confirmation);
+                        app.mode = InputMode::Confirm;
+                    }
+                    // moves everything shown under a priority filter to another
+                    // priority, picked in the prompt, e.g. bumping all of
+                    // Medium to High
+                    KeyCode::Char('P') if !app.tasks.is_empty() => {
+                        if let Filter::Priority(from) = app.filter {
+                            let ids = app.tasks.iter().map(|t| t.id).collect();
+                            app.confirmation =
+                                Some(Confirmation::set_priority(ids, from, app.view()));
+                            app.mode = InputMode::Confirm;
+                        }
+                    }
+                    KeyCode::Char(c @ '1'..='3') if key.modifiers.contains(KeyModifiers::ALT) => {
                         if let Some(index) = app.todo_state.selected()
                             && let Some(task) = app.tasks.get(index)
+                            && let Some(priority) = Priority::from_weight(c as u8 - b'1')
                             && let Err(e) = action_tx.send(Action::Update(
                                 task.id,
-                                None,
-                                Some(!task.done),
-                                None,
-                                app.filter,
+                                UpdateTodo {
+                                    priority: Some(priority),
+                                    version: Some(task.version),
+                                    ..Default::default()
+                                },
+                                app.view(),
                             ))
                         {
-                            error!("failed to send toggle (update) action: {e}");
+                            error!("failed to set priority: {e}");
                         }
                     }
-                    KeyCode::Up | KeyCode::Char('k') => {
+                    KeyCode::Char(c @ '1'..='3') => {
+                        let priority = Priority::from_weight(c as u8 - b'1');
+                        if let Some(index) =
+                            app.tasks.iter().position(|t| Some(t.priority) == priority)
+                        {
+                            app.todo_state.select(Some(index));
+                        }
+                    }
+                    // the list truncates long text, this shows all of it
+                    KeyCode::Char('o') if app.todo_state.selected().is_some() => {
+                        app.mode = InputMode::Detail;
+                    }
+                    KeyCode::Char('v') => {
+                        app.board = !app.board;
+                        if app.board {
+                            let priority = app
+                                .todo_state
+                                .selected()
+                                .and_then(|i| app.tasks.get(i))
+                                .map_or(app.board_column, |t| t.priority);
+                            app.focus_column(priority);
+                        }
+                    }
+                    KeyCode::Char('t') => {
+                        app.completed_today.clear();
+                        app.mode = InputMode::Summary;
+                        action_tx.send(Action::FetchCompletedToday)?;
+                    }
+                    KeyCode::Char('T') => {
+                        app.templates.clear();
+                        app.template_state.select(None);
+                        app.mode = InputMode::Templates;
+                        action_tx.send(Action::FetchTemplates)?;
+                    }
+                    KeyCode::Enter => {
+                        // the server refuses to finish blocked tasks anyway, and
+                        // flips done itself so a stale list can't undo a change
+                        if let Some(index) = app.todo_state.selected()
+                            && let Some(task) = app.tasks.get(index)
+                            && (!task.blocked || task.done)
+                            && let Err(e) = action_tx.send(Action::Toggle(task.id, app.view()))
+                        {
+                            error!("failed to send toggle action: {e}");
+                        }
+                    }
+                    // empty lists have nowhere to move, and len() - 1 would underflow
+                    KeyCode::Up | KeyCode::Char('k') if !app.tasks.is_empty() => {
                         let i = match app.todo_state.selected() {
                             Some(i) => {
                                 if i == 0 {
@@ -193,7 +1469,7 @@ async fn main() -> Result<()> {
                         };
                         app.todo_state.select(Some(i));
                     }
-                    KeyCode::Down | KeyCode::Char('j') => {
+                    KeyCode::Down | KeyCode::Char('j') if !app.tasks.is_empty() => {
                         let i = match app.todo_state.selected() {
                             Some(i) => {
                                 if i >= app.tasks.len() - 1 {
@@ -205,24 +1481,45 @@ async fn main() -> Result<()> {
                             None => 0,
                         };
                         app.todo_state.select(Some(i));
+
+                        // load the next page before the selection reaches the end
+                        if i + LOAD_MORE_THRESHOLD >= app.tasks.len()
+                            && !app.loading_more
+                            && let Some(cursor) = app.next_cursor
+                        {
+                            app.loading_more = true;
+                            action_tx.send(Action::FetchMore(app.view(), cursor))?;
+                        }
+                    }
+                    // the list is ordered by id, there's no manual order to
+                    // move tasks within, so these only move the selection
+                    KeyCode::Char('g') if !app.tasks.is_empty() => app.todo_state.select(Some(0)),
+                    KeyCode::Char('G') if !app.tasks.is_empty() => {
+                        app.todo_state.select(Some(app.tasks.len() - 1));
+                        if !app.loading_more
+                            && let Some(cursor) = app.next_cursor
+                        {
+                            app.loading_more = true;
+                            action_tx.send(Action::FetchMore(app.view(), cursor))?;
+                        }
                     }
                     KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                         debug!("lower priority");
+                        // already at the lowest there's nothing to send
                         if let Some(index) = app.todo_state.selected()
                             && let Some(task) = app.tasks.get(index)
+                            && task.priority != Priority::Low
                         {
-                            let new_prio = match task.priority {
-                                Priority::Low => Priority::High,
-                                Priority::Medium => Priority::Low,
-                                Priority::High => Priority::Medium,
-                            };
+                            let new_prio = task.priority.lowered();
                             debug!("new_prio: {new_prio}");
                             if let Err(e) = action_tx.send(Action::Update(
                                 task.id,
-                                None,
-                                None,
-                                Some(new_prio),
-                                app.filter,
+                                UpdateTodo {
+                                    priority: Some(new_prio),
+                                    version: Some(task.version),
+                                    ..Default::default()
+                                },
+                                app.view(),
                             )) {
                                 error!("failed to lower priority: {e}");
                             }
@@ -232,19 +1529,18 @@ async fn main() -> Result<()> {
                         debug!("increase priority");
                         if let Some(index) = app.todo_state.selected()
                             && let Some(task) = app.tasks.get(index)
+                            && task.priority != Priority::High
                         {
-                            let new_prio = match task.priority {
-                                Priority::Low => Priority::Medium,
-                                Priority::Medium => Priority::High,
-                                Priority::High => Priority::Low,
-                            };
+                            let new_prio = task.priority.raised();
                             debug!("new_prio: {new_prio}");
                             if let Err(e) = action_tx.send(Action::Update(
                                 task.id,
-                                None,
-                                None,
-                                Some(new_prio),
-                                app.filter,
+                                UpdateTodo {
+                                    priority: Some(new_prio),
+                                    version: Some(task.version),
+                                    ..Default::default()
+                                },
+                                app.view(),
                             )) {
                                 error!("failed to increase priority: {e}");
                             }
@@ -253,44 +1549,105 @@ async fn main() -> Result<()> {
                     _ => {}
                 },
                 InputMode::Editing => match key.code {
+                    KeyCode::Esc if app.has_unsaved_edit() => {
+                        app.confirmation = Some(Confirmation {
+                            prompt: "Discard changes? (y/n)".to_string(),
+                            on_confirm: OnConfirm::DiscardEdit,
+                        });
+                        app.mode = InputMode::Confirm;
+                    }
                     KeyCode::Esc => {
                         app.mode = InputMode::Normal;
-                        app.input.clear(); // clear buf
+                        app.currently_editing_id = None;
+                        app.reset_input();
+                    }
+                    KeyCode::Tab => {
+                        app.editing_due = !app.editing_due;
+                        app.cursor_to_end();
+                    }
+                    KeyCode::Left => app.cursor = app.cursor.saturating_sub(1),
+                    KeyCode::Right => {
+                        let len = app.focused_input().graphemes(true).count();
+                        app.cursor = (app.cursor + 1).min(len);
                     }
+                    KeyCode::Home => app.cursor = 0,
+                    KeyCode::End => app.cursor_to_end(),
+                    // priority only applies to new tasks, edits keep theirs
+                    KeyCode::Char('x')
+                        if key.modifiers.contains(KeyModifiers::CONTROL)
+                            && app.currently_editing_id.is_none() =>
+                    {
+                        app.priority = app.priority.lowered();
+                    }
+                    KeyCode::Char('a')
+                        if key.modifiers.contains(KeyModifiers::CONTROL)
+                            && app.currently_editing_id.is_none() =>
+                    {
+                        app.priority = app.priority.raised();
+                    }
+                    // ...but the keys are swallowed rather than typed
+                    KeyCode::Char('x' | 'a') if key.modifiers.contains(KeyModifiers::CONTROL) => {}
                     KeyCode::Char(c) => {
-                        app.input.push(c);
+                        app.insert_at_cursor(c);
+                        app.input_error = None;
                     }
                     KeyCode::Backspace => {
-                        app.input.pop();
+                        app.remove_before_cursor();
+                        app.input_error = None;
+                    }
+                    KeyCode::Delete => {
+                        app.remove_at_cursor();
+                        app.input_error = None;
                     }
                     KeyCode::Enter => {
-                        if app.currently_editing_id.is_some() {
-                            let task = app
-                                .tasks
-                                .iter()
-                                .find(|t| t.id == app.currently_editing_id.unwrap());
-                            let task = task.unwrap();
-                            debug!("update: {task}");
+                        // keep the popup open so the date can be fixed
+                        let due = match parse_due(&app.due_input) {
+                            Ok(due) => due,
+                            Err(e) => {
+                                app.input_error = Some(e);
+                                continue;
+                            }
+                        };
+                        // the task may have been refetched away while editing,
+                        // so only its id is relied on here
+                        if let Some(id) = app.currently_editing_id {
+                            debug!("update: {id}");
                             if let Err(e) = action_tx.send(Action::Update(
-                                task.id,
-                                Some(app.input.clone()),
-                                Some(task.done),
-                                None,
-                                app.filter,
+                                id,
+                                UpdateTodo {
+                                    text: Some(app.input.clone()),
+                                    due,
+                                    // emptying the field removes the due date
+                                    clear_due: due.is_none()
+                                        && !app.original_due_input.trim().is_empty(),
+                                    version: Some(app.editing_version),
+                                    ..Default::default()
+                                },
+                                app.view(),
                             )) {
                                 error!("failed to send update action: {e}");
                             }
                             app.currently_editing_id = None;
                         } else {
                             debug!("create");
-                            if let Err(e) =
-                                action_tx.send(Action::Create(app.input.clone(), app.filter))
-                            {
+                            if let Err(e) = action_tx.send(Action::Create(
+                                CreateTodo {
+                                    text: app.input.clone(),
+                                    // leave it to the server when the text has its own !priority
+                                    priority: parse_quick_add(&app.input)
+                                        .1
+                                        .is_none()
+                                        .then_some(app.priority),
+                                    due,
+                                    source: "tui",
+                                },
+                                app.view(),
+                            )) {
                                 error!("failed to send create action: {e}");
                             }
                         }
                         // reset state
-                        app.input.clear();
+                        app.reset_input();
                         app.mode = InputMode::Normal;
                     }
                     _ => {}
@@ -306,7 +1663,7 @@ async fn main() -> Result<()> {
                             debug!("setting filter to {filter}");
                             app.filter = *filter;
 
-                            if let Err(e) = action_tx.send(Action::Fetch(app.filter)) {
+                            if let Err(e) = action_tx.send(Action::Fetch(app.first_page())) {
                                 error!("failed to send fetch action: {e}");
                             }
                         }
@@ -335,70 +1692,257 @@ async fn main() -> Result<()> {
                                     i + 1
                                 }
                             }
-                            None => 0,
-                        };
-                        app.filter_state.select(Some(i));
+                            None => 0,
+                        };
+                        app.filter_state.select(Some(i));
+                    }
+                    KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        if let Some(index) = app.filter_state.selected()
+                            && let Some(filter) = get_menu_filters(app.priority).get(index)
+                            && let Filter::Priority(priority) = filter
+                        {
+                            debug!("{priority}");
+                            app.priority = app.priority.lowered();
+                        }
+                    }
+                    KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        if let Some(index) = app.filter_state.selected()
+                            && let Some(filter) = get_menu_filters(app.priority).get(index)
+                            && let Filter::Priority(priority) = filter
+                        {
+                            debug!("{priority}");
+                            app.priority = app.priority.raised();
+                        }
+                    }
+                    _ => {}
+                },
+                InputMode::Search => match key.code {
+                    KeyCode::Esc => {
+                        app.search_input.clear();
+                        app.mode = InputMode::Normal;
+                    }
+                    KeyCode::Tab => {
+                        app.fuzzy = !app.fuzzy;
+                        app.search_state.select(Some(0));
+                    }
+                    KeyCode::BackTab if !app.fuzzy => app.match_mode = app.match_mode.next(),
+                    KeyCode::Up if app.fuzzy => app.search_state.select_previous(),
+                    KeyCode::Down if app.fuzzy => app.search_state.select_next(),
+                    KeyCode::Char(c) => {
+                        app.search_input.push(c);
+                        app.search_state.select(Some(0));
+                    }
+                    KeyCode::Backspace => {
+                        app.search_input.pop();
+                        app.search_state.select(Some(0));
+                    }
+                    KeyCode::Enter => {
+                        if app.fuzzy {
+                            // fuzzy search is a picker over what's loaded, it
+                            // jumps to the chosen task rather than filtering
+                            let picked = app
+                                .search_state
+                                .selected()
+                                .and_then(|i| app.fuzzy_matches().get(i).map(|t| t.id));
+                            if let Some(id) = picked {
+                                let index = app.tasks.iter().position(|t| t.id == id);
+                                app.todo_state.select(index);
+                            }
+                        } else {
+                            let search = app.search_input.trim();
+                            app.search = (!search.is_empty()).then(|| search.to_string());
+                            action_tx.send(Action::Fetch(app.first_page()))?;
+                        }
+                        app.search_input.clear();
+                        app.mode = InputMode::Normal;
+                    }
+                    _ => {}
+                },
+                InputMode::Summary => {
+                    if let KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('t') = key.code {
+                        app.mode = InputMode::Normal;
+                    }
+                }
+                InputMode::Templates => match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('T') => {
+                        app.mode = InputMode::Normal;
+                    }
+                    KeyCode::Down | KeyCode::Char('j') if !app.templates.is_empty() => {
+                        let i = app
+                            .template_state
+                            .selected()
+                            .map_or(0, |i| (i + 1).min(app.templates.len() - 1));
+                        app.template_state.select(Some(i));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => app.template_state.select_previous(),
+                    KeyCode::Enter => {
+                        if let Some(index) = app.template_state.selected()
+                            && let Some(template) = app.templates.get(index)
+                        {
+                            action_tx.send(Action::ApplyTemplate(template.id, app.view()))?;
+                            app.mode = InputMode::Normal;
+                        }
+                    }
+                    _ => {}
+                },
+                InputMode::JumpToId => match key.code {
+                    KeyCode::Esc => app.mode = InputMode::Normal,
+                    KeyCode::Char(c) if c.is_ascii_digit() => {
+                        app.jump_input.push(c);
+                        app.jump_error = None;
+                    }
+                    KeyCode::Char(_) => app.jump_error = Some("ids are numbers".to_string()),
+                    KeyCode::Backspace => {
+                        app.jump_input.pop();
+                        app.jump_error = None;
+                    }
+                    KeyCode::Enter => match app.jump_input.parse::<i64>() {
+                        Ok(id) => match app.tasks.iter().position(|t| t.id == id) {
+                            // the list scrolls to keep the selection in view
+                            Some(index) => {
+                                app.todo_state.select(Some(index));
+                                if app.board {
+                                    app.board_column = app.tasks[index].priority;
+                                }
+                                app.mode = InputMode::Normal;
+                            }
+                            None => app.jump_error = Some(format!("{id} not found")),
+                        },
+                        Err(_) => app.jump_error = Some("type an id".to_string()),
+                    },
+                    _ => {}
+                },
+                InputMode::Detail => {
+                    if let KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('o') = key.code {
+                        app.mode = InputMode::Normal;
                     }
-                    KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        if let Some(index) = app.filter_state.selected()
-                            && let Some(filter) = get_menu_filters(app.priority).get(index)
-                            && let Filter::Priority(priority) = filter
-                        {
-                            debug!("{priority}");
-                            match app.priority {
-                                Priority::Low => app.priority = Priority::High,
-                                Priority::Medium => app.priority = Priority::Low,
-                                Priority::High => app.priority = Priority::Medium,
+                }
+                InputMode::Confirm => match key.code {
+                    KeyCode::Char('y') => {
+                        match app.confirmation.take().map(|c| c.on_confirm) {
+                            Some(OnConfirm::Send(action)) => action_tx.send(action)?,
+                            Some(OnConfirm::DiscardEdit) => {
+                                app.currently_editing_id = None;
+                                app.reset_input();
                             }
+                            Some(OnConfirm::SetPriority {
+                                ids, target, view, ..
+                            }) => {
+                                let payload = UpdateTodo {
+                                    priority: Some(target),
+                                    ..Default::default()
+                                };
+                                action_tx.send(Action::UpdateMany(ids, payload, view))?;
+                            }
+                            None => {}
                         }
+                        app.mode = InputMode::Normal;
                     }
-                    KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                        if let Some(index) = app.filter_state.selected()
-                            && let Some(filter) = get_menu_filters(app.priority).get(index)
-                            && let Filter::Priority(priority) = filter
-                        {
-                            debug!("{priority}");
-                            match app.priority {
-                                Priority::Low => app.priority = Priority::Medium,
-                                Priority::Medium => app.priority = Priority::High,
-                                Priority::High => app.priority = Priority::Low,
-                            }
+                    KeyCode::Left | KeyCode::Right | KeyCode::Char('h' | 'l') => {
+                        if let Some(confirmation) = &mut app.confirmation {
+                            let up = matches!(key.code, KeyCode::Right | KeyCode::Char('l'));
+                            confirmation.cycle_target(up);
                         }
                     }
+                    KeyCode::Char('n') | KeyCode::Esc => {
+                        app.mode = match app.confirmation.take().map(|c| c.on_confirm) {
+                            Some(OnConfirm::DiscardEdit) => InputMode::Editing,
+                            _ => InputMode::Normal,
+                        };
+                    }
                     _ => {}
                 },
                 InputMode::Help => match key.code {
                     KeyCode::Esc => app.mode = InputMode::Normal,
-                    KeyCode::Left | KeyCode::Char('h') => match app.help_mode {
-                        InputMode::Normal => app.help_mode = InputMode::Help,
-                        InputMode::Editing => app.help_mode = InputMode::Normal,
-                        InputMode::Filter => app.help_mode = InputMode::Editing,
-                        InputMode::Help => app.help_mode = InputMode::Filter,
-                    },
-                    KeyCode::Right | KeyCode::Char('l') => match app.help_mode {
-                        InputMode::Normal => app.help_mode = InputMode::Editing,
-                        InputMode::Editing => app.help_mode = InputMode::Filter,
-                        InputMode::Filter => app.help_mode = InputMode::Help,
-                        InputMode::Help => app.help_mode = InputMode::Normal,
-                    },
+                    // help_size is the length of the page drawn last frame
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        let i = app
+                            .help_state
+                            .selected()
+                            .map_or(0, |i| (i + 1).min(app.help_size.saturating_sub(1)));
+                        app.help_state.select(Some(i));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => app.help_state.select_previous(),
+                    KeyCode::Left | KeyCode::Char('h') => {
+                        app.help_state.select(Some(0));
+                        match app.help_mode {
+                            InputMode::Normal => app.help_mode = InputMode::Help,
+                            InputMode::Editing => app.help_mode = InputMode::Normal,
+                            InputMode::Filter => app.help_mode = InputMode::Editing,
+                            InputMode::Search => app.help_mode = InputMode::Filter,
+                            InputMode::Summary => app.help_mode = InputMode::Search,
+                            InputMode::Templates => app.help_mode = InputMode::Summary,
+                            InputMode::JumpToId => app.help_mode = InputMode::Templates,
+                            InputMode::Detail => app.help_mode = InputMode::JumpToId,
+                            InputMode::Confirm => app.help_mode = InputMode::Detail,
+                            InputMode::Help => app.help_mode = InputMode::Confirm,
+                        }
+                    }
+                    KeyCode::Right | KeyCode::Char('l') => {
+                        app.help_state.select(Some(0));
+                        match app.help_mode {
+                            InputMode::Normal => app.help_mode = InputMode::Editing,
+                            InputMode::Editing => app.help_mode = InputMode::Filter,
+                            InputMode::Filter => app.help_mode = InputMode::Search,
+                            InputMode::Search => app.help_mode = InputMode::Summary,
+                            InputMode::Summary => app.help_mode = InputMode::Templates,
+                            InputMode::Templates => app.help_mode = InputMode::JumpToId,
+                            InputMode::JumpToId => app.help_mode = InputMode::Detail,
+                            InputMode::Detail => app.help_mode = InputMode::Confirm,
+                            InputMode::Confirm => app.help_mode = InputMode::Help,
+                            InputMode::Help => app.help_mode = InputMode::Normal,
+                        }
+                    }
                     _ => {}
                 },
             }
         }
     }
     disable_raw_mode()?;
+
+    let saved = SavedState {
+        filter: app.filter,
+        priority: Some(app.priority),
+    };
+    if let Err(e) = saved.save() {
+        error!("failed to save state: {e}");
+    }
     Ok(())
 }
 
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const DEFAULT_IDLE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long without input or responses before polling backs off
+const IDLE_AFTER: Duration = Duration::from_secs(3);
+const DEFAULT_FOCUS_COUNT: usize = 3;
+const DUE_SOON: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const PAGE_SIZE: i64 = 50;
+const LABELS: [&str; 3] = ["red", "green", "blue"];
+const FUZZY_RESULTS: usize = 10;
+const DUE_INPUT_FORMAT: &str = "%Y-%m-%d %H:%M";
+const LOAD_MORE_THRESHOLD: usize = 5;
+
 const TITLE_INDEX: usize = 0;
 const LIST_INDEX: usize = 1;
+const DISTRIBUTION_INDEX: usize = 2;
+const PROGRESS_INDEX: usize = 3;
+const STATUS_INDEX: usize = 4;
+/// Below this many rows the distribution chart is left out to keep the list usable
+const DISTRIBUTION_MIN_HEIGHT: u16 = 16;
 
 fn ui(frame: &mut Frame, app: &mut App) {
+    let show_distribution =
+        !app.hide_distribution && frame.area().height >= DISTRIBUTION_MIN_HEIGHT;
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(1), // title
             Constraint::Min(1),    // list
+            // one bar per priority
+            Constraint::Length(if show_distribution { 3 } else { 0 }),
+            Constraint::Length(1), // progress
+            Constraint::Length(1), // status
         ])
         .split(frame.area());
 
@@ -409,20 +1953,116 @@ fn ui(frame: &mut Frame, app: &mut App) {
 
     frame.render_widget(title, chunks[TITLE_INDEX]);
 
+    // render priority distribution
+
+    if show_distribution {
+        let bars = [
+            (Priority::High, Color::Red),
+            (Priority::Medium, Color::Yellow),
+            (Priority::Low, Color::Gray),
+        ]
+        .map(|(priority, color)| {
+            let count = app.tasks.iter().filter(|t| t.priority == priority).count();
+            Bar::default()
+                .value(count as u64)
+                .label(Line::from(priority.to_string()))
+                .style(Style::default().fg(color))
+                .value_style(Style::default().fg(Color::Black).bg(color))
+        });
+        let chart = BarChart::default()
+            .direction(Direction::Horizontal)
+            .bar_width(1)
+            .bar_gap(0)
+            .data(BarGroup::default().bars(&bars));
+        frame.render_widget(chart, chunks[DISTRIBUTION_INDEX]);
+    }
+
+    // render progress
+
+    let done = app.tasks.iter().filter(|t| t.done).count();
+    let total = app.tasks.len();
+    let ratio = if total == 0 {
+        0.0
+    } else {
+        done as f64 / total as f64
+    };
+    let progress = Gauge::default()
+        .gauge_style(Style::default().fg(Color::Green).bg(Color::DarkGray))
+        .ratio(ratio)
+        .label(format!("{done}/{total} done ({:.0}%)", ratio * 100.0));
+    frame.render_widget(progress, chunks[PROGRESS_INDEX]);
+
+    // render status line
+
+    let (dot_color, state) = if app.last_request_ok {
+        (Color::Green, "connected")
+    } else {
+        (Color::Red, "disconnected")
+    };
+    let mut status = vec![
+        Span::styled("● ", Style::default().fg(dot_color)),
+        Span::raw(state),
+    ];
+    if let Some(at) = app.last_contact {
+        status.push(Span::styled(
+            format!(" · last contact {}s ago", at.elapsed().as_secs()),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    if app.queued > 0 {
+        status.push(Span::styled(
+            format!(" · {} offline changes queued", app.queued),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if let Some(banner) = &app.error_banner {
+        status.push(Span::styled(
+            format!(" · {banner}"),
+            Style::default().fg(Color::White).bg(Color::Red),
+        ));
+    }
+    frame.render_widget(Paragraph::new(Line::from(status)), chunks[STATUS_INDEX]);
+
     // render list
 
-    let list_filter = match app.filter {
-        Filter::Priority(_) => format!("Priority {}", app.priority),
-        _ => app.filter.to_string(),
+    let list_filter = app.filter.to_string();
+    let mut list_title = match &app.search {
+        Some(search) => format!("Tasks ({list_filter}, matching {search:?})"),
+        None => format!("Tasks ({list_filter})"),
     };
-    let list_title = format!("Tasks ({list_filter})");
+    if app.focus {
+        list_title.push_str(&format!(" [focus: top {}]", app.focus_count));
+    }
     let list_block = Block::default().borders(Borders::ALL).title(list_title);
-    let list = List::new(app.tasks.iter().map(|t| t.to_listitem()))
-        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
-        .block(list_block);
+    // minus the borders
+    let list_width = chunks[LIST_INDEX].width.saturating_sub(2) as usize;
+    let focused_ids = app.focused_ids();
+    let id_width = app.id_width();
+    let list = List::new(app.tasks.iter().map(|t| {
+        focus_dimmed(
+            t.to_listitem(list_width, id_width, app.date_format),
+            t,
+            focused_ids.as_ref(),
+        )
+    }))
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(list_block);
 
-    // swap to regular widget when in Filter or Help modes
-    if app.mode == InputMode::Filter || app.mode == InputMode::Help {
+    // swap to regular widget when a popup is open
+    let popup_open = matches!(
+        app.mode,
+        InputMode::Filter
+            | InputMode::Search
+            | InputMode::Summary
+            | InputMode::Templates
+            | InputMode::JumpToId
+            | InputMode::Detail
+            | InputMode::Confirm
+            | InputMode::Help
+    );
+    if app.board {
+        render_board(frame, app, chunks[LIST_INDEX], !popup_open);
+    } else if popup_open {
         frame.render_widget(list, chunks[LIST_INDEX]);
     } else {
         frame.render_stateful_widget(list, chunks[LIST_INDEX], &mut app.todo_state);
@@ -432,30 +2072,208 @@ fn ui(frame: &mut Frame, app: &mut App) {
 
     match app.mode {
         InputMode::Editing => {
-            let input_block = Block::default().borders(Borders::ALL).title("Add Task");
-            let input_style = Style::default().fg(Color::Yellow);
+            let input_title = if app.currently_editing_id.is_some() {
+                "Edit Task".to_string()
+            } else {
+                format!("Add Task {}", app.priority)
+            };
+            let input_block = Block::default().borders(Borders::ALL).title(input_title);
+            let focused = Style::default().fg(Color::Yellow);
+            let unfocused = Style::default().fg(Color::Gray);
+            let (text_style, due_style) = if app.editing_due {
+                (unfocused, focused)
+            } else {
+                (focused, unfocused)
+            };
+
+            let mut lines = vec![
+                Line::styled(app.input.as_str(), text_style),
+                Line::styled(format!("due: {}", app.due_input), due_style),
+            ];
+            if let Some(e) = &app.input_error {
+                lines.push(Line::styled(e.as_str(), Style::default().fg(Color::Red)));
+            }
 
-            let input = Paragraph::new(app.input.as_str())
-                .style(input_style)
-                .block(input_block);
-            let area = popup_area(chunks[LIST_INDEX], 50, 3);
+            let area = popup_area(chunks[LIST_INDEX], 50, (lines.len() as u16) + 2);
+            let input = Paragraph::new(lines).block(input_block);
+
+            // the border takes one cell on each side, and the due line starts
+            // with its label
+            let (focused_input, row, prefix) = if app.editing_due {
+                (&app.due_input, 1, "due: ".len())
+            } else {
+                (&app.input, 0, 0)
+            };
+            let before_cursor = &focused_input[..byte_index(focused_input, app.cursor)];
+            let column = prefix + before_cursor.width();
 
             frame.render_widget(Clear, area);
             frame.render_widget(input, area);
+            frame.set_cursor_position((area.x + 1 + column as u16, area.y + 1 + row));
         }
         InputMode::Filter => {
             let filter_block = Block::default().borders(Borders::ALL).title("Filter by");
             let filters: Vec<String> = get_menu_filters(app.priority)
-                .iter()
-                .map(std::string::ToString::to_string)
+                .into_iter()
+                .map(|filter| match app.filter_counts {
+                    Some(counts) => format!("{filter} ({})", counts.matching(filter)),
+                    None => filter.to_string(),
+                })
                 .collect();
+            let filter_width = filters.iter().map(|f| f.width()).max().unwrap_or(0) as u16 + 4;
 
             let input = List::new(filters)
                 .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
                 .block(filter_block);
-            let area = popup_area(chunks[LIST_INDEX], 15, 6);
+            let area = popup_area(chunks[LIST_INDEX], filter_width.max(15), 6);
             frame.render_stateful_widget(input, area, &mut app.filter_state);
         }
+        InputMode::Search => {
+            let search_title = if app.fuzzy {
+                "Search (fuzzy)".to_string()
+            } else {
+                format!("Search ({})", app.match_mode)
+            };
+            let search_block = Block::default().borders(Borders::ALL).title(search_title);
+            let matches: Vec<String> = if app.fuzzy {
+                app.fuzzy_matches()
+                    .iter()
+                    .take(FUZZY_RESULTS)
+                    .map(|t| t.text.clone())
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            let area = popup_area(chunks[LIST_INDEX], 50, (matches.len() as u16) + 3);
+            let [input_area, results_area] =
+                Layout::vertical([Constraint::Length(1), Constraint::Min(0)])
+                    .areas(search_block.inner(area));
+            let input =
+                Paragraph::new(app.search_input.as_str()).style(Style::default().fg(Color::Yellow));
+            let results = List::new(matches)
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+            frame.render_widget(Clear, area);
+            frame.render_widget(search_block, area);
+            frame.render_widget(input, input_area);
+            frame.render_stateful_widget(results, results_area, &mut app.search_state);
+        }
+        InputMode::Summary => {
+            let summary_block = Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Completed today ({})", app.completed_today.len()));
+            let items: Vec<String> = if app.completed_today.is_empty() {
+                vec!["nothing yet".to_string()]
+            } else {
+                app.completed_today.iter().map(|t| t.text.clone()).collect()
+            };
+
+            let summary_width = popup_width(
+                items.iter().map(|s| s.width()).max().unwrap_or(0),
+                chunks[LIST_INDEX],
+            );
+            let summary_height = (items.len() as u16) + 2;
+            let summary = List::new(items)
+                .block(summary_block)
+                .style(Style::default().fg(Color::Green));
+            let area = popup_area(chunks[LIST_INDEX], summary_width, summary_height);
+
+            frame.render_widget(Clear, area);
+            frame.render_widget(summary, area);
+        }
+        InputMode::Templates => {
+            let templates_block = Block::default()
+                .borders(Borders::ALL)
+                .title("Templates (<CR> to apply)");
+            let items: Vec<String> = if app.templates.is_empty() {
+                vec!["no templates saved".to_string()]
+            } else {
+                app.templates
+                    .iter()
+                    .map(|t| format!("{} ({} tasks)", t.name, t.tasks.len()))
+                    .collect()
+            };
+
+            let templates_width = popup_width(
+                items.iter().map(|s| s.width()).max().unwrap_or(0),
+                chunks[LIST_INDEX],
+            );
+            let templates_height = (items.len() as u16) + 2;
+            let templates = List::new(items)
+                .block(templates_block)
+                .style(Style::default().fg(Color::Green))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            let area = popup_area(chunks[LIST_INDEX], templates_width, templates_height);
+
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(templates, area, &mut app.template_state);
+        }
+        InputMode::JumpToId => {
+            let jump_block = Block::default().borders(Borders::ALL).title("Jump to id");
+            let mut lines = vec![Line::styled(
+                app.jump_input.as_str(),
+                Style::default().fg(Color::Yellow),
+            )];
+            if let Some(e) = &app.jump_error {
+                lines.push(Line::styled(e.as_str(), Style::default().fg(Color::Red)));
+            }
+            let area = popup_area(chunks[LIST_INDEX], 20, (lines.len() as u16) + 2);
+            let input = Paragraph::new(lines).block(jump_block);
+
+            frame.render_widget(Clear, area);
+            frame.render_widget(input, area);
+            frame.set_cursor_position((area.x + 1 + app.jump_input.len() as u16, area.y + 1));
+        }
+        InputMode::Detail => {
+            let task = app.todo_state.selected().and_then(|i| app.tasks.get(i));
+            let text = task.map(|t| t.text.as_str()).unwrap_or_default();
+            let mut dates = Vec::new();
+            let now = Utc::now();
+            if let Some(due) = task.and_then(|t| t.due) {
+                let when = format_when(due.with_timezone(&Local), now, app.date_format);
+                dates.push(Line::styled(
+                    format!("due {when}"),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            if let Some(completed_at) = task.and_then(|t| t.completed_at) {
+                let when = format_when(completed_at.with_timezone(&Local), now, app.date_format);
+                dates.push(Line::styled(
+                    format!("done {when}"),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            let detail_width = chunks[LIST_INDEX].width.saturating_sub(4).min(60);
+            // rough line count for sizing, the paragraph does the real wrapping
+            let text_width = detail_width.saturating_sub(2).max(1) as usize;
+            let detail_height =
+                text.width().div_ceil(text_width).max(1) as u16 + dates.len() as u16 + 2;
+            let mut lines = vec![Line::raw(text)];
+            lines.extend(dates);
+            let detail = Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title("Task"));
+            let area = popup_area(chunks[LIST_INDEX], detail_width, detail_height);
+
+            frame.render_widget(Clear, area);
+            frame.render_widget(detail, area);
+        }
+        InputMode::Confirm => {
+            let prompt = app
+                .confirmation
+                .as_ref()
+                .map(|c| c.prompt.as_str())
+                .unwrap_or_default();
+            let confirm = Paragraph::new(prompt)
+                .style(Style::default().fg(Color::Red))
+                .alignment(Alignment::Center)
+                .block(Block::default().borders(Borders::ALL).title("Confirm"));
+            let area = popup_area(chunks[LIST_INDEX], (prompt.width() as u16) + 4, 3);
+
+            frame.render_widget(Clear, area);
+            frame.render_widget(confirm, area);
+        }
         InputMode::Help => {
             let help_block = Block::default()
                 .borders(Borders::ALL)
@@ -466,42 +2284,101 @@ fn ui(frame: &mut Frame, app: &mut App) {
             // keymap vecs
             let help_normal_keys = [
                 "q: quit",
+                "h/?: help",
                 "<CR>: toggle done",
                 "d: delete task",
-                "i: add task",
+                "u: undo last delete",
+                "i: add task (!high and #tag in the text set priority and tags)",
                 "e: edit task",
+                "y: duplicate task",
+                "c: cycle colour label",
                 "r: refresh",
+                "t: completed today",
+                "T: apply a template",
+                "o: open full task text",
+                "v: toggle list/kanban view",
+                "F: toggle focus on top priority tasks",
+                "p: show/hide priority distribution",
+                "kanban h/l: switch column, H/L: move task",
+                "/: search",
+                "tab/shift+tab: cycle filter",
+                "A: mark all shown tasks done/undone",
+                "P: move all shown tasks to another priority (priority filter only)",
+                "g/G: jump to first/last loaded task",
+                ":/#: jump to a task by id",
+                "1/2/3: jump to first low/medium/high task",
+                "alt+1/2/3: set priority to low/medium/high",
                 "ctrl+x/a: decrease/increase priority",
             ];
-            let help_editing_keys = ["esc: exit editing mode", "<CR>: submit"];
+            let help_editing_keys = [
+                "esc: exit editing mode",
+                "<CR>: submit",
+                "tab: switch between text and due date",
+                "[left]/[right]/home/end: move cursor",
+                "due: 2024-06-01, 2024-06-01 14:00, +3d, +2w, empty to clear",
+                "ctrl+x/a: decrease/increase new task priority",
+            ];
             let help_filter_keys = [
                 "esc: exit filter mode",
                 "ctrl+x/a: decrease/increase priority",
                 "<CR>: filter",
             ];
-            let help_help_keys = ["[left][h]/[right][l]: navigate help", "esc: exit help mode"];
+            let help_search_keys = [
+                "esc: cancel search",
+                "tab: toggle fuzzy/exact search",
+                "shift+tab: cycle substring/prefix/exact match",
+                "<CR>: filter by search (exact) or jump to match (fuzzy)",
+                "[up]/[down]: choose fuzzy match",
+            ];
+            let help_summary_keys = ["esc/q/t: close summary"];
+            let help_templates_keys = [
+                "[up][k]/[down][j]: choose template",
+                "<CR>: create the template's tasks",
+                "esc/q/T: close templates",
+            ];
+            let help_jump_keys = ["0-9: type the id", "<CR>: select that task", "esc: cancel"];
+            let help_detail_keys = ["esc/o: close task"];
+            let help_confirm_keys = [
+                "y: confirm",
+                "n/esc: cancel",
+                "[left][h]/[right][l]: change the target priority (P only)",
+            ];
+            let help_help_keys = [
+                "[left][h]/[right][l]: navigate help",
+                "[up][k]/[down][j]: scroll",
+                "esc: exit help mode",
+            ];
 
             let keys: &[&str] = match app.help_mode {
                 InputMode::Normal => &help_normal_keys,
                 InputMode::Editing => &help_editing_keys,
                 InputMode::Filter => &help_filter_keys,
+                InputMode::Search => &help_search_keys,
+                InputMode::Summary => &help_summary_keys,
+                InputMode::Templates => &help_templates_keys,
+                InputMode::JumpToId => &help_jump_keys,
+                InputMode::Detail => &help_detail_keys,
+                InputMode::Confirm => &help_confirm_keys,
                 InputMode::Help => &help_help_keys,
             };
 
             app.help_size = keys.len();
 
             // +4 for borders and titles
-            let help_menu_width = (keys.iter().map(|s| s.len()).max().unwrap_or(30) as u16) + 5;
+            let help_menu_width = (keys.iter().map(|s| s.width()).max().unwrap_or(30) as u16) + 5;
             let help_menu_length = (keys.len() as u16) + 2;
 
             let help = List::new(keys.iter().copied())
                 .block(help_block)
-                .style(help_style);
+                .style(help_style)
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
+            // popup_area clamps to the list area, so long pages scroll with
+            // the selection on small terminals
             let area = popup_area(chunks[LIST_INDEX], help_menu_width, help_menu_length);
 
             frame.render_widget(Clear, area);
-            frame.render_widget(help, area);
+            frame.render_stateful_widget(help, area, &mut app.help_state);
         }
         _ => {}
     }
@@ -516,6 +2393,167 @@ fn get_menu_filters(cur_priority: Priority) -> Vec<Filter> {
     ]
 }
 
+fn notify_due_soon(task: &Task) {
+    let mut body = task.text.clone();
+    if let Some(due) = task.due {
+        body.push_str(&format!(
+            "\ndue at {}",
+            due.with_timezone(&Local).format("%H:%M")
+        ));
+    }
+    if let Err(e) = notify_rust::Notification::new()
+        .summary("Task due soon")
+        .body(&body)
+        .show()
+    {
+        error!("failed to show notification for task {}: {e}", task.id);
+    }
+}
+
+/// Parses the due date typed in the editing popup. Accepts `2024-06-01`,
+/// `2024-06-01 14:00`, or an offset from today like `+3d`/`+2w`. Dates
+/// without a time are due at the end of that day, and an empty field means no
+/// due date.
+fn parse_due(input: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid = || "invalid due date, try 2024-06-01 or +3d".to_string();
+    let day = if let Some(offset) = input.strip_prefix('+') {
+        let days = if let Some(n) = offset.strip_suffix('d') {
+            n.parse::<u64>().map_err(|_| invalid())?
+        } else if let Some(n) = offset.strip_suffix('w') {
+            n.parse::<u64>()
+                .ok()
+                .and_then(|weeks| weeks.checked_mul(7))
+                .ok_or_else(invalid)?
+        } else {
+            return Err(invalid());
+        };
+        Local::now()
+            .date_naive()
+            .checked_add_days(chrono::Days::new(days))
+            .ok_or_else(invalid)?
+    } else if let Ok(at) = NaiveDateTime::parse_from_str(input, DUE_INPUT_FORMAT) {
+        return local_to_utc(at).map(Some).ok_or_else(invalid);
+    } else {
+        NaiveDate::parse_from_str(input, "%Y-%m-%d").map_err(|_| invalid())?
+    };
+
+    day.and_hms_opt(23, 59, 59)
+        .and_then(local_to_utc)
+        .map(Some)
+        .ok_or_else(invalid)
+}
+
+fn local_to_utc(at: NaiveDateTime) -> Option<DateTime<Utc>> {
+    at.and_local_timezone(Local)
+        .earliest()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// Dims a task focus mode is hiding, leaves everything as is when it's off
+fn focus_dimmed<'a>(
+    item: ListItem<'a>,
+    task: &Task,
+    focused_ids: Option<&HashSet<i64>>,
+) -> ListItem<'a> {
+    match focused_ids {
+        Some(ids) if !ids.contains(&task.id) => {
+            item.style(Style::default().add_modifier(Modifier::DIM))
+        }
+        _ => item,
+    }
+}
+
+/// Draws the tasks as Low/Medium/High columns, highlighting the selected task
+/// in the focused one
+fn render_board(frame: &mut Frame, app: &App, area: Rect, show_selection: bool) {
+    let columns = Layout::horizontal([Constraint::Ratio(1, 3); 3]).split(area);
+    let focused_ids = app.focused_ids();
+    let id_width = app.id_width();
+    for (priority, &column_area) in [Priority::Low, Priority::Medium, Priority::High]
+        .into_iter()
+        .zip(columns.iter())
+    {
+        let indices = app.column_indices(priority);
+        let focused = priority == app.board_column;
+        let border_style = if focused {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(border_style)
+            .title(format!("{priority:?} ({})", indices.len()));
+
+        let width = column_area.width.saturating_sub(2) as usize;
+        let list = List::new(indices.iter().map(|&i| {
+            let task = &app.tasks[i];
+            focus_dimmed(
+                task.to_listitem(width, id_width, app.date_format),
+                task,
+                focused_ids.as_ref(),
+            )
+        }))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(block);
+        let mut state = ListState::default();
+        if focused && show_selection {
+            state.select(
+                app.todo_state
+                    .selected()
+                    .and_then(|s| indices.iter().position(|&i| i == s)),
+            );
+        }
+        frame.render_stateful_widget(list, column_area, &mut state);
+    }
+}
+
+/// Byte offset of the grapheme at `graphemes`, or the end of the string past
+/// the last
+fn byte_index(s: &str, graphemes: usize) -> usize {
+    s.grapheme_indices(true)
+        .nth(graphemes)
+        .map_or(s.len(), |(i, _)| i)
+}
+
+/// Cuts `text` down to at most `width` columns, ending it with "…" when
+/// anything was dropped
+fn truncate_to_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for grapheme in text.graphemes(true) {
+        // leave a column for the ellipsis
+        if used + grapheme.width() + 1 > width {
+            break;
+        }
+        used += grapheme.width();
+        truncated.push_str(grapheme);
+    }
+    if width > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Width for a list popup whose widest line is `widest` cells, at least 25
+/// wide but never wider than `area`, borders included
+fn popup_width(widest: usize, area: Rect) -> u16 {
+    let max = area.width.saturating_sub(4);
+    u16::try_from(widest)
+        .unwrap_or(u16::MAX)
+        .min(max)
+        .max(25.min(max))
+        + 2
+}
+
 fn popup_area(area: Rect, px_x: u16, px_y: u16) -> Rect {
     let vertical = Layout::vertical([Constraint::Length(px_y)]).flex(Flex::Center);
     let horizontal = Layout::horizontal([Constraint::Length(px_x)]).flex(Flex::Center);
@@ -524,80 +2562,566 @@ fn popup_area(area: Rect, px_x: u16, px_y: u16) -> Rect {
     area
 }
 
-async fn fetch_tasks(filter: Filter) -> Result<Vec<Task>, Box<dyn std::error::Error>> {
-    debug!("fetch_tasks: {filter}");
-    let params = TaskQuery::from(filter);
+type TaskPage = (Vec<Task>, Option<i64>);
 
-    let client = reqwest::Client::new();
-    let response = client
-        .get("http://localhost:3000/todos")
+/// Shared by every request so connections are reused. The timeout turns a
+/// hung server into a `TuiEvent::Error` instead of a worker stuck forever.
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(token) = &config().token {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))
+            .expect("Config::load checks the token");
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .default_headers(headers)
+        .build()
+        .unwrap()
+});
+
+/// `path` on the configured server
+fn url(path: &str) -> String {
+    format!("{}{path}", config().server_url.trim_end_matches('/'))
+}
+
+/// Passes 2xx responses through. Anything else becomes an error carrying the
+/// status and the body as text, so a proxy's HTML page or the server's
+/// `{"error": ...}` body shows up in the TUI instead of a serde error.
+async fn check_status(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, Box<dyn std::error::Error + Send + Sync>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let body = body.trim();
+    if body.is_empty() {
+        Err(format!("server returned {status}").into())
+    } else {
+        Err(format!("server returned {status}: {body}").into())
+    }
+}
+
+/// Replays the offline queue, then tells the UI what's left and how many
+/// changes the server refused
+async fn flush_queue(queue: &mut OfflineQueue, event_tx: &mpsc::UnboundedSender<TuiEvent>) {
+    let dropped = queue.replay().await;
+    if dropped > 0 {
+        let reason = format!("{dropped} offline changes were refused, see the log");
+        event_tx.send(TuiEvent::Rejected(reason)).unwrap();
+    }
+    event_tx
+        .send(TuiEvent::Queued(queue.pending.len()))
+        .unwrap();
+}
+
+/// Whether `e` means the server couldn't be reached at all, as opposed to it
+/// answering with an error
+fn is_offline(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_connect() || e.is_timeout())
+}
+
+/// Fetches one page of tasks, returning them with the cursor for the next page
+/// (`None` once the last page has been reached). First pages are sent with
+/// `etag` and come back as `Ok(None)` when the list hasn't changed since.
+async fn fetch_tasks(
+    view: &View,
+    after_id: Option<i64>,
+    etag: &mut Option<String>,
+) -> Result<Option<TaskPage>, Box<dyn std::error::Error + Send + Sync>> {
+    debug!("fetch_tasks: {view:?} after {after_id:?}");
+    // a refetch from the top covers every page already on screen
+    let limit = match after_id {
+        Some(_) => PAGE_SIZE,
+        None => PAGE_SIZE.max(view.loaded as i64),
+    };
+    let params = TaskQuery {
+        after_id,
+        limit: Some(limit),
+        search: view.search.clone(),
+        match_mode: Some(view.match_mode),
+        ..TaskQuery::from(view.filter)
+    };
+
+    let client = &*HTTP_CLIENT;
+    let mut request = client
+        .get(url("/todos"))
         .query(&params)
+        .query(&[("with_urgency", true)]);
+    if after_id.is_none()
+        && let Some(etag) = etag.as_deref()
+    {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let response = check_status(response).await?;
+    if after_id.is_none() {
+        *etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+    }
+    let page = response.json::<todo_common::TaskPage>().await?;
+
+    Ok(Some((page.tasks, page.next_cursor)))
+}
+
+async fn fetch_completed_today() -> Result<Vec<Task>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client.get(url("/todos/completed-today")).send().await?;
+
+    Ok(check_status(response).await?.json::<Vec<Task>>().await?)
+}
+
+/// Returns the server's reason when it refuses the task, e.g. because the
+/// list is full
+async fn create_task(
+    payload: &CreateTodo,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client.post(url("/todos")).json(payload).send().await?;
+    if response.status() != reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+        check_status(response).await?;
+        return Ok(None);
+    }
+    let body: ErrorBody = response.json().await?;
+    Ok(Some(body.error))
+}
+
+async fn fetch_counts() -> Result<FilterCounts, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client.get(url("/todos/stats")).send().await?;
+    Ok(check_status(response).await?.json().await?)
+}
+
+async fn fetch_templates() -> Result<Vec<Template>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client.get(url("/templates")).send().await?;
+    Ok(check_status(response).await?.json().await?)
+}
+
+/// Returns the server's reason when it refuses, e.g. because the template
+/// wouldn't fit in the list
+async fn apply_template(
+    id: i64,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client
+        .post(url(&format!("/templates/{id}/apply")))
         .send()
-        .await?
-        .json::<Vec<Task>>()
         .await?;
+    if response.status() != reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+        check_status(response).await?;
+        return Ok(None);
+    }
+    let body: ErrorBody = response.json().await?;
+    Ok(Some(body.error))
+}
 
-    Ok(response)
+async fn delete_task(id: i64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client.delete(url(&format!("/todos/{id}"))).send().await?;
+    check_status(response).await?;
+    Ok(())
 }
 
-async fn create_task(text: String) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    client
-        .post("http://localhost:3000/todos")
-        .json(&CreateTodo { text })
+async fn toggle_task(id: i64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client
+        .post(url(&format!("/todos/{id}/toggle")))
         .send()
         .await?;
+    check_status(response).await?;
     Ok(())
 }
 
-async fn delete_task(id: i64) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    client
-        .delete(format!("http://localhost:3000/todos/{id}"))
+async fn restore_task(id: i64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client
+        .post(url(&format!("/todos/{id}/restore")))
         .send()
         .await?;
+    check_status(response).await?;
     Ok(())
 }
 
-async fn update_task(
-    id: i64,
-    text: Option<String>,
-    done: Option<bool>,
-    priority: Option<Priority>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    client
-        .patch(format!("http://localhost:3000/todos/{id}"))
-        .json(&UpdateTodo {
-            text,
-            done,
-            priority,
+#[derive(serde::Serialize)]
+struct BulkUpdate<'a> {
+    ids: &'a [i64],
+    changes: &'a UpdateTodo,
+}
+
+/// Applies `payload` to the listed tasks in one `PATCH /todos`, all or none.
+/// The view's filter goes along, so a task that's stopped matching it since
+/// it was shown is left alone.
+async fn update_many(
+    view: &View,
+    ids: &[i64],
+    payload: &UpdateTodo,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let params = TaskQuery {
+        search: view.search.clone(),
+        match_mode: Some(view.match_mode),
+        ..TaskQuery::from(view.filter)
+    };
+    let client = &*HTTP_CLIENT;
+    let response = client
+        .patch(url("/todos"))
+        .query(&params)
+        .json(&BulkUpdate {
+            ids,
+            changes: payload,
         })
         .send()
         .await?;
+    check_status(response).await?;
+    Ok(())
+}
+
+async fn duplicate_task(id: i64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client
+        .post(url(&format!("/todos/{id}/duplicate")))
+        .send()
+        .await?;
+    check_status(response).await?;
     Ok(())
 }
 
+/// Returns false if the server refused the update with a conflict, e.g.
+/// because `payload.version` is out of date
+async fn update_task(
+    id: i64,
+    payload: &UpdateTodo,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = &*HTTP_CLIENT;
+    let response = client
+        .patch(url(&format!("/todos/{id}")))
+        .json(payload)
+        .send()
+        .await?;
+    if response.status() == reqwest::StatusCode::CONFLICT {
+        return Ok(false);
+    }
+    check_status(response).await?;
+    Ok(true)
+}
+
 trait TaskExt {
-    fn to_listitem(&'_ self) -> ListItem<'_>;
+    /// Long text is cut short with an ellipsis to fit in `width` columns, and
+    /// the id is right-aligned in `id_width` columns
+    fn to_listitem(&'_ self, width: usize, id_width: usize, dates: DateFormat) -> ListItem<'_>;
 }
 
 impl TaskExt for Task {
-    fn to_listitem(&'_ self) -> ListItem<'_> {
+    fn to_listitem(&'_ self, width: usize, id_width: usize, dates: DateFormat) -> ListItem<'_> {
         let color = if self.done {
-            Color::Green
+            config().theme.done
         } else {
-            Color::Yellow
+            config().theme.pending
         };
         let status_text = if self.done { "[x]" } else { "[ ]" };
-        let line = Line::from(vec![
+        let mut spans = vec![Span::styled(
+            format!("{:>id_width$} ", self.id),
+            Style::default().fg(Color::DarkGray),
+        )];
+        if let Some(label) = &self.label {
+            let label_color = match label.as_str() {
+                "red" => Color::Red,
+                "green" => Color::Green,
+                "blue" => Color::Blue,
+                _ => Color::White,
+            };
+            spans.push(Span::styled("● ", Style::default().fg(label_color)));
+        }
+        // terminals without strikethrough just skip it, leaving the colour to
+        // show the task is done
+        let text_style = if self.done && config().theme.strike_done {
+            Style::default().add_modifier(Modifier::CROSSED_OUT)
+        } else {
+            Style::default()
+        };
+        let text_index = spans.len() + 2;
+        spans.extend([
             Span::styled(status_text, Style::default().fg(color)),
-            Span::raw(format!(" {} ", self.text)),
+            Span::raw(" "),
+            Span::styled(self.text.as_str(), text_style),
+            Span::raw(" "),
             Span::styled(
                 format!("{}", self.priority),
                 Style::default().fg(Color::Gray),
             ),
         ]);
-        ListItem::new(line)
+        for tag in &self.tags {
+            spans.push(Span::styled(
+                format!(" #{tag}"),
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if let Some(due) = self.due {
+            let due_color = match self.urgency {
+                Some(Urgency::Overdue) => Color::Red,
+                Some(Urgency::DueSoon) => Color::Yellow,
+                Some(Urgency::Normal) | None => Color::DarkGray,
+            };
+            spans.push(Span::styled(
+                format!(
+                    " due {}",
+                    format_when(due.with_timezone(&Local), Utc::now(), dates)
+                ),
+                Style::default().fg(due_color),
+            ));
+        }
+        let mut line = Line::from(spans);
+        let overflow = line.width().saturating_sub(width);
+        if overflow > 0 {
+            let text_width = self.text.width().saturating_sub(overflow);
+            line.spans[text_index] =
+                Span::styled(truncate_to_width(&self.text, text_width), text_style);
+        }
+        let item = ListItem::new(line);
+        if self.blocked {
+            item.style(Style::default().add_modifier(Modifier::DIM))
+        } else {
+            item
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_priority_target_skips_the_current_priority() {
+        let view = View::default();
+        let mut confirmation = Confirmation::set_priority(vec![1, 2], Priority::Medium, view);
+        let target = |c: &Confirmation| match c.on_confirm {
+            OnConfirm::SetPriority { target, .. } => target,
+            _ => unreachable!(),
+        };
+        assert_eq!(target(&confirmation), Priority::High);
+        confirmation.cycle_target(true);
+        assert_eq!(target(&confirmation), Priority::High);
+        confirmation.cycle_target(false);
+        assert_eq!(target(&confirmation), Priority::Low);
+        assert!(confirmation.prompt.starts_with("Set 2 shown tasks to (L)"));
+
+        let from_low = Confirmation::set_priority(vec![1], Priority::Low, View::default());
+        let mut from_high = Confirmation::set_priority(vec![1], Priority::High, View::default());
+        assert_eq!(target(&from_low), Priority::Medium);
+        from_high.cycle_target(true);
+        assert_eq!(target(&from_high), Priority::Medium);
+    }
+
+    #[test]
+    fn rebound_keys_resolve_to_their_defaults() {
+        let keys = Keys {
+            add: 'a',
+            delete: 'x',
+            ..Keys::default()
+        };
+        assert_eq!(keys.resolve(KeyCode::Char('a')), KeyCode::Char('i'));
+        assert_eq!(keys.resolve(KeyCode::Char('x')), KeyCode::Char('d'));
+        // the old keys do nothing now
+        assert_eq!(keys.resolve(KeyCode::Char('i')), KeyCode::Null);
+        assert_eq!(keys.resolve(KeyCode::Char('d')), KeyCode::Null);
+        assert_eq!(keys.resolve(KeyCode::Char('q')), KeyCode::Char('q'));
+        assert_eq!(keys.resolve(KeyCode::Enter), KeyCode::Enter);
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let mut config: Config = toml::from_str(
+            r#"
+            server_url = "http://file:3000"
+            poll_ms = 100
+            refresh_secs = 30
+            keys = { add = "a" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keys.add, 'a');
+        assert_eq!(config.idle_poll_ms, Config::default().idle_poll_ms);
+
+        let env = std::collections::HashMap::from([
+            ("TODO_TUI_SERVER_URL", "http://env:3000"),
+            ("TODO_TUI_POLL_MS", "not a number"),
+            ("TODO_TUI_REFRESH_SECS", ""),
+            ("TODO_TUI_DEFAULT_PRIORITY", "high"),
+        ]);
+        config.apply_env(|name| env.get(name).map(|v| v.to_string()));
+        assert_eq!(config.server_url, "http://env:3000");
+        // unparsable and empty values leave the file's setting alone
+        assert_eq!(config.poll_ms, 100);
+        assert_eq!(config.refresh_secs, 30);
+        assert_eq!(config.default_priority, Priority::High);
+    }
+
+    #[test]
+    fn bad_configs_are_refused() {
+        assert!(toml::from_str::<Config>("colour = \"red\"").is_err());
+        assert!(toml::from_str::<Config>("[theme]\ndone = \"no such colour\"").is_err());
+
+        let duplicate: Config = toml::from_str("[keys]\nadd = \"d\"").unwrap();
+        let e = duplicate.validate().unwrap_err();
+        assert_eq!(e.to_string(), "'d' is bound to both add and delete");
+
+        let token = Config {
+            token: Some("line\nbreak".to_string()),
+            ..Config::default()
+        };
+        assert!(token.validate().is_err());
+        Config::default().validate().unwrap();
+    }
+
+    fn queued() -> OfflineQueue {
+        let task = CreateTodo {
+            text: "buy milk".to_string(),
+            priority: Some(Priority::High),
+            due: None,
+            source: "tui",
+        };
+        let changes = UpdateTodo {
+            done: Some(true),
+            clear_due: true,
+            version: Some(3),
+            ..Default::default()
+        };
+        OfflineQueue {
+            pending: vec![
+                Pending::Create { task },
+                Pending::Update { id: 1, changes },
+                Pending::Delete { id: 2 },
+                Pending::Delete { id: 3 },
+            ],
+        }
+    }
+
+    #[test]
+    fn offline_queue_round_trips_through_toml() {
+        let saved = toml::to_string(&queued()).unwrap();
+        assert!(saved.contains(r#"kind = "Update""#));
+        let loaded: OfflineQueue = toml::from_str(&saved).unwrap();
+        let [
+            Pending::Create { task },
+            Pending::Update { id: 1, changes },
+            Pending::Delete { id: 2 },
+            Pending::Delete { id: 3 },
+        ] = loaded.pending.as_slice()
+        else {
+            panic!("queue came back as {:?}", loaded.pending);
+        };
+        assert_eq!(task.text, "buy milk");
+        assert_eq!(task.priority, Some(Priority::High));
+        assert_eq!(task.source, "tui");
+        assert_eq!(changes.done, Some(true));
+        assert!(changes.clear_due);
+        assert_eq!(changes.version, Some(3));
+        assert_eq!(toml::from_str::<OfflineQueue>("").unwrap().pending.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn replay_stops_offline_and_drops_refusals() {
+        // nothing listens on port 1, so this is the error a down server gives
+        let offline = reqwest::Client::new()
+            .get("http://127.0.0.1:1")
+            .send()
+            .await
+            .unwrap_err();
+        let mut offline = Some(offline);
+        let mut queue = queued();
+        let mut sent = Vec::new();
+        let dropped = queue
+            .replay_with(async |change| {
+                sent.push(format!("{change:?}"));
+                match change {
+                    Pending::Create { .. } => Ok(None),
+                    Pending::Update { .. } => Ok(Some("changed elsewhere".to_string())),
+                    Pending::Delete { id: 2 } => Err("404 Not Found".into()),
+                    Pending::Delete { .. } => Err(offline.take().unwrap().into()),
+                }
+            })
+            .await;
+        assert_eq!(dropped, 2);
+        assert_eq!(sent.len(), 4);
+        assert!(sent[0].starts_with("Create") && sent[1].starts_with("Update"));
+        // the change that couldn't reach the server waits for the next replay
+        assert!(matches!(queue.pending[..], [Pending::Delete { id: 3 }]));
+
+        let dropped = queue.replay_with(async |_| Ok(None)).await;
+        assert_eq!(dropped, 0);
+        assert!(queue.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_unreachable_servers_count_as_offline() {
+        let offline = reqwest::Client::new()
+            .get("http://127.0.0.1:1")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(matches!(error_event(offline.into()), TuiEvent::Offline(_)));
+        // a refusal means the server answered
+        let refused = error_event("409 Conflict".into());
+        assert!(matches!(refused, TuiEvent::Error(msg) if msg == "409 Conflict"));
+    }
+
+    #[test]
+    fn due_dates_parse() {
+        assert_eq!(parse_due("  "), Ok(None));
+        let end_of_day = |date: &str| {
+            let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+            local_to_utc(day.and_hms_opt(23, 59, 59).unwrap())
+        };
+        assert_eq!(parse_due("2024-06-01"), Ok(end_of_day("2024-06-01")));
+        let at = NaiveDateTime::parse_from_str("2024-06-01 14:00", DUE_INPUT_FORMAT).unwrap();
+        assert_eq!(parse_due("2024-06-01 14:00"), Ok(local_to_utc(at)));
+        let in_two_weeks = Local::now().date_naive() + chrono::Days::new(14);
+        assert_eq!(
+            parse_due("+2w"),
+            Ok(end_of_day(&in_two_weeks.format("%Y-%m-%d").to_string()))
+        );
+        for bad in [
+            "tomorrow",
+            "+3",
+            "+-1d",
+            "2024-13-01",
+            "+3000000000000000000w",
+        ] {
+            assert!(parse_due(bad).is_err(), "{bad} should be refused");
+        }
+    }
+
+    #[test]
+    fn byte_index_counts_graphemes() {
+        // "é" here is an e followed by a combining accent, one grapheme
+        let text = "ne\u{301}🎉x";
+        assert_eq!(byte_index(text, 0), 0);
+        assert_eq!(byte_index(text, 1), 1);
+        assert_eq!(byte_index(text, 2), 4);
+        assert_eq!(byte_index(text, 3), 8);
+        assert_eq!(byte_index(text, 4), text.len());
+        assert_eq!(byte_index(text, 10), text.len());
+    }
+
+    #[test]
+    fn truncation_respects_wide_and_combining_graphemes() {
+        assert_eq!(truncate_to_width("日本語", 6), "日本語");
+        // 本 would need columns 3-4 and leave none for the ellipsis
+        assert_eq!(truncate_to_width("日本語", 4), "日…");
+        assert_eq!(truncate_to_width("日本語", 1), "…");
+        let accents = "e\u{301}e\u{301}e\u{301}";
+        assert_eq!(truncate_to_width(accents, 2), "e\u{301}…");
+        assert_eq!(truncate_to_width("🎉🎉", 3), "🎉…");
+        assert_eq!(truncate_to_width("anything", 0), "");
     }
 }